@@ -0,0 +1,40 @@
+/// One missing or invalid key in the ini file.
+#[derive(Clone, Debug)]
+pub struct ConfigurationIssue {
+   pub file : String,
+   pub section : String,
+   pub key : String,
+   pub problem : String,
+}
+
+impl std::fmt::Display for ConfigurationIssue {
+   fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      if self.section.is_empty() {
+         write!(f, "{}: {}", self.file, self.problem)
+      }
+      else if self.key.is_empty() {
+         write!(f, "{}: [{}] {}", self.file, self.section, self.problem)
+      }
+      else {
+         write!(f, "{}: [{}] {} {}", self.file, self.section, self.key, self.problem)
+      }
+   }
+}
+
+/// Every problem found while validating the ini file.
+#[derive(Clone, Debug)]
+pub struct ConfigurationError {
+   pub issues : Vec<ConfigurationIssue>,
+}
+
+impl std::fmt::Display for ConfigurationError {
+   fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      write!(f, "{} configuration problem(s)", self.issues.len())?;
+      for issue in self.issues.iter() {
+         write!(f, "\n   {}", issue)?;
+      }
+      Ok(())
+   }
+}
+
+impl std::error::Error for ConfigurationError {}
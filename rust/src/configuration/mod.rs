@@ -0,0 +1,1113 @@
+pub mod error;
+use crate::configuration::error::{ConfigurationError, ConfigurationIssue};
+use crate::datatypes::maintenance_window::MaintenanceWindow;
+
+pub static SQLITE3_SECTION: &str = "SISSqlite3Database";
+pub static POSTGRES_SECTION: &str = "SISPostgresDatabase";
+pub static API_SECTION: &str = "AWSDistributionAPI";
+pub static POLLER_SECTION: &str = "SISPoller";
+pub static NOTIFICATION_SECTION: &str = "Notification";
+pub static DIGEST_SECTION: &str = "Digest";
+pub static STORM_SECTION: &str = "Storm";
+pub static QUIET_HOURS_SECTION: &str = "QuietHours";
+pub static MAINTENANCE_SECTION: &str = "Maintenance";
+/// Each notification route is a section named `Route.<name>`, e.g., `Route.keepers`.
+pub static ROUTE_SECTION_PREFIX: &str = "Route.";
+
+static REDACTED: &str = "********";
+
+/// The default ini file.  Unlike an explicitly named file it may be absent,
+/// in which case every setting must come from the environment.
+pub static DEFAULT_INI_FILE: &str = "./sisPoller.ini";
+
+/// The environment variable that overrides each (section, key) in the ini file.
+/// Appending _FILE to a variable, or _file to a key, names a file holding the
+/// value instead, e.g., a Docker or Kubernetes secret.  Values are resolved in
+/// order: command line, environment variable, secret file named by the
+/// environment, secret file named in the ini file, and finally the ini file.
+pub static ENVIRONMENT_VARIABLES: &[(&str, &str, &str)] = &[
+   ("SISPoller",           "backend",           "SIS_POLLER_BACKEND"),
+   ("SISPoller",           "auto_migrate",      "SIS_POLLER_AUTO_MIGRATE"),
+   ("SISPoller",           "create_missing",    "SIS_POLLER_CREATE_MISSING"),
+   ("SISPoller",           "lock_timeout",      "SIS_POLLER_LOCK_TIMEOUT"),
+   ("SISPoller",           "poll_interval",     "SIS_POLLER_POLL_INTERVAL"),
+   ("SISPoller",           "metrics_address",   "SIS_POLLER_METRICS_ADDRESS"),
+   ("SISPoller",           "ready_intervals",   "SIS_POLLER_READY_INTERVALS"),
+   ("SISSqlite3Database",  "file_name",         "SIS_POLLER_SQLITE3_FILE_NAME"),
+   ("SISPostgresDatabase", "host",              "SIS_POLLER_DATABASE_HOST"),
+   ("SISPostgresDatabase", "port",              "SIS_POLLER_DATABASE_PORT"),
+   ("SISPostgresDatabase", "name",              "SIS_POLLER_DATABASE_NAME"),
+   ("SISPostgresDatabase", "schema",            "SIS_POLLER_DATABASE_SCHEMA"),
+   ("SISPostgresDatabase", "user",              "SIS_POLLER_DATABASE_USER"),
+   ("SISPostgresDatabase", "password",          "SIS_POLLER_DATABASE_PASSWORD"),
+   ("SISPostgresDatabase", "sslmode",           "SIS_POLLER_DATABASE_SSLMODE"),
+   ("SISPostgresDatabase", "sslrootcert",       "SIS_POLLER_DATABASE_SSLROOTCERT"),
+   ("SISPostgresDatabase", "sslcert",           "SIS_POLLER_DATABASE_SSLCERT"),
+   ("SISPostgresDatabase", "sslkey",            "SIS_POLLER_DATABASE_SSLKEY"),
+   ("AWSDistributionAPI",  "uri",               "SIS_NOTIFICATION_API_URI"),
+   ("AWSDistributionAPI",  "key",               "SIS_NOTIFICATION_API_KEY"),
+   ("AWSDistributionAPI",  "notificationTopic", "SIS_NOTIFICATION_API_TOPIC"),
+   ("AWSDistributionAPI",  "notificationType",  "SIS_NOTIFICATION_API_TYPE"),
+   ("Notification",        "format",            "SIS_POLLER_NOTIFICATION_FORMAT"),
+   ("Notification",        "subject_template",  "SIS_POLLER_SUBJECT_TEMPLATE"),
+   ("Notification",        "body_template",     "SIS_POLLER_BODY_TEMPLATE"),
+   ("Digest",              "window",            "SIS_POLLER_DIGEST_WINDOW"),
+   ("Digest",              "max_changes",       "SIS_POLLER_DIGEST_MAX_CHANGES"),
+   ("Digest",              "critical_stations", "SIS_POLLER_DIGEST_CRITICAL_STATIONS"),
+   ("Storm",               "fraction",          "SIS_POLLER_STORM_FRACTION"),
+   ("Storm",               "min_stations",      "SIS_POLLER_STORM_MIN_STATIONS"),
+   ("QuietHours",          "start",             "SIS_POLLER_QUIET_START"),
+   ("QuietHours",          "end",               "SIS_POLLER_QUIET_END"),
+   ("QuietHours",          "timezone",          "SIS_POLLER_QUIET_TIMEZONE"),
+   ("QuietHours",          "critical_stations", "SIS_POLLER_QUIET_CRITICAL_STATIONS"),
+   ("Maintenance",         "windows",           "SIS_POLLER_MAINTENANCE_WINDOWS"),
+];
+
+fn environment_variable(section : &str, key : &str) -> Option<&'static str> {
+   ENVIRONMENT_VARIABLES.iter()
+      .find(|(s, k, _)| s.eq_ignore_ascii_case(section) && k.eq_ignore_ascii_case(key))
+      .map(|(_, _, variable)| *variable)
+}
+
+fn read_secret_file(file_name : &str) -> Result<String, std::io::Error> {
+   let contents = std::fs::read_to_string(file_name)?;
+   Ok(contents.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// The storage backend selected on the command line or in the ini file.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Backend {
+   Sqlite3,
+   Postgres,
+}
+
+impl std::str::FromStr for Backend {
+   type Err = String;
+   fn from_str(value : &str) -> Result<Self, Self::Err> {
+      <Backend as clap::ValueEnum>::from_str(value.trim(), true)
+   }
+}
+
+/// The sslmode key of the `[SISPostgresDatabase]` section.  The names and
+/// checks follow libpq.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum SslMode {
+   Disable,
+   Prefer,
+   Require,
+   VerifyCa,
+   VerifyFull,
+}
+
+impl std::str::FromStr for SslMode {
+   type Err = String;
+   fn from_str(value : &str) -> Result<Self, Self::Err> {
+      <SslMode as clap::ValueEnum>::from_str(value.trim(), true)
+   }
+}
+
+/// The format key of the `[Notification]` section.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum NotificationFormat {
+   Text,
+   Markdown,
+   Html,
+}
+
+impl std::str::FromStr for NotificationFormat {
+   type Err = String;
+   fn from_str(value : &str) -> Result<Self, Self::Err> {
+      <NotificationFormat as clap::ValueEnum>::from_str(value.trim(), true)
+   }
+}
+
+/// The `[SISSqlite3Database]` section.
+#[derive(Clone, Debug)]
+pub struct Sqlite3Parameters {
+   pub file_name : String,
+}
+
+/// The `[SISPostgresDatabase]` section.
+#[derive(Clone, Debug)]
+pub struct PostgresParameters {
+   pub host : String,
+   pub port : u16,
+   pub name : String,
+   pub schema : String,
+   pub user : String,
+   pub password : String,
+   pub ssl_mode : SslMode,
+   /// CA bundle used to verify the server (PEM)
+   pub ssl_root_certificate : Option<String>,
+   /// Client certificate (PEM)
+   pub ssl_certificate : Option<String>,
+   /// Client private key (PEM)
+   pub ssl_key : Option<String>,
+}
+
+/// Whether a command reads the `[AWSDistributionAPI]` section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiSection {
+   /// The command posts, so the section must be present and valid
+   Required,
+   /// Validated if present, e.g., by config check
+   IfPresent,
+   /// Not read at all
+   Ignored,
+}
+
+/// The `[AWSDistributionAPI]` section.
+#[derive(Clone, Debug)]
+pub struct ApiParameters {
+   pub uri : String,
+   pub key : String,
+   pub notification_topic : String,
+   pub notification_type : String,
+}
+
+/// The `[Notification]` section.  A missing template selects the built-in
+/// template for the format.
+#[derive(Clone, Debug)]
+pub struct NotificationParameters {
+   pub format : NotificationFormat,
+   /// File holding the subject template
+   pub subject_template : Option<String>,
+   /// File holding the body template
+   pub body_template : Option<String>,
+}
+
+/// A `[Route.<name>]` section.  Changes to stations in networks, or matching
+/// stations, e.g., US.BOZ or NN.*, are posted with this route's topic, type,
+/// and templates.  Unset keys fall back to the `[AWSDistributionAPI]` and
+/// `[Notification]` sections.
+#[derive(Clone, Debug)]
+pub struct RouteParameters {
+   pub name : String,
+   pub networks : Vec<String>,
+   pub stations : Vec<String>,
+   pub uri : Option<String>,
+   pub key : Option<String>,
+   pub notification_topic : Option<String>,
+   pub notification_type : Option<String>,
+   pub notification : NotificationParameters,
+}
+
+/// The `[Digest]` section.  Changes are held in the store and announced
+/// together once the oldest has waited window seconds or max_changes are
+/// held.  Changes to critical stations, e.g., US.BOZ or NN.*, are announced at
+/// once.
+#[derive(Clone, Debug)]
+pub struct DigestParameters {
+   /// Seconds to hold a change; 0 holds until max_changes are held
+   pub window : u64,
+   /// How many held changes send the digest early; 0 waits for the window
+   pub max_changes : u64,
+   pub critical_stations : Vec<String>,
+}
+
+/// The `[Storm]` section.  A network in which more than fraction of the
+/// listed stations, and at least min_stations, changed is announced as one
+/// bulk update rather than station by station.
+#[derive(Clone, Debug)]
+pub struct StormParameters {
+   pub fraction : f64,
+   pub min_stations : u64,
+}
+
+/// The `[QuietHours]` section.  Between start and end, local time in the
+/// timezone, changes to all but the critical stations are held and announced
+/// afterward.  The hours may span midnight, e.g., 22:00 to 07:00.
+#[derive(Clone, Debug)]
+pub struct QuietHoursParameters {
+   pub start : chrono::NaiveTime,
+   pub end : chrono::NaiveTime,
+   pub timezone : chrono_tz::Tz,
+   pub critical_stations : Vec<String>,
+}
+
+impl QuietHoursParameters {
+   pub fn contains(&self, time : chrono::DateTime<chrono::Utc>) -> bool {
+      let local = time.with_timezone(&self.timezone).time();
+      if self.start <= self.end {
+         self.start <= local && local < self.end
+      }
+      else {
+         local >= self.start || local < self.end
+      }
+   }
+}
+
+/// The validated contents of the ini file.
+#[derive(Clone, Debug)]
+pub struct Configuration {
+   pub file : String,
+   pub backend : Backend,
+   pub auto_migrate : bool,
+   pub create_missing : bool,
+   /// Seconds to wait for another instance to release the run lock
+   pub lock_timeout : u64,
+   /// Seconds between polls in daemon mode
+   pub poll_interval : u64,
+   /// Where the daemon serves /metrics, /healthz, and /readyz, e.g., 0.0.0.0:9184
+   pub metrics_address : Option<String>,
+   /// The daemon is not ready once this many poll intervals pass without a successful poll
+   pub ready_intervals : u64,
+   pub sqlite3 : Option<Sqlite3Parameters>,
+   pub postgres : Option<PostgresParameters>,
+   pub api : Option<ApiParameters>,
+   pub notification : NotificationParameters,
+   pub routes : Vec<RouteParameters>,
+   /// Set if changes are announced in digests
+   pub digest : Option<DigestParameters>,
+   /// Set if bulk updates are condensed
+   pub storm : Option<StormParameters>,
+   pub quiet_hours : Option<QuietHoursParameters>,
+   /// The windows of the `[Maintenance]` section; `sis_poller mute` stores more
+   pub maintenance : Vec<MaintenanceWindow>,
+}
+
+/// Reads keys from the environment and ini file while collecting every
+/// problem it finds.
+struct Reader<'a> {
+   config : &'a configparser::ini::Ini,
+   environment : &'a dyn Fn(&str) -> Option<String>,
+   file : &'a str,
+   issues : Vec<ConfigurationIssue>,
+}
+
+impl Reader<'_> {
+   fn has_section(&self, section : &str) -> bool {
+      // The ini parser lower-cases section names
+      if self.config.sections().contains(&section.to_lowercase()) {
+         return true;
+      }
+      // A section may also be supplied entirely through the environment
+      ENVIRONMENT_VARIABLES.iter()
+         .filter(|(s, _, _)| *s == section)
+         .any(|(_, _, variable)| {
+            (self.environment)(variable).is_some()
+            || (self.environment)(&format!("{}_FILE", variable)).is_some()
+         })
+   }
+
+   fn issue(&mut self, section : &str, key : &str, problem : &str) {
+      self.issues.push(ConfigurationIssue {file: self.file.to_string(),
+                                           section: section.to_string(),
+                                           key: key.to_string(),
+                                           problem: problem.to_string()});
+   }
+
+   fn secret_file(&mut self, section : &str, key : &str, file_name : &str, source : &str) -> Option<String> {
+      match read_secret_file(file_name) {
+         Ok(value) => Some(value),
+         Err(error) => {
+            self.issue(section, key, &format!("cannot read secret file {} named by {}: {}", file_name, source, error));
+            None
+         }
+      }
+   }
+
+   fn optional(&mut self, section : &str, key : &str) -> Option<String> {
+      let non_empty = |value : Option<String>| value.filter(|value| !value.trim().is_empty());
+      if let Some(variable) = environment_variable(section, key) {
+         if let Some(value) = non_empty((self.environment)(variable)) {
+            return Some(value);
+         }
+         let file_variable = format!("{}_FILE", variable);
+         if let Some(file_name) = non_empty((self.environment)(&file_variable)) {
+            return non_empty(self.secret_file(section, key, &file_name, &file_variable));
+         }
+      }
+      let file_key = format!("{}_file", key);
+      if let Some(file_name) = non_empty(self.config.get(section, &file_key)) {
+         return non_empty(self.secret_file(section, key, &file_name, &file_key));
+      }
+      non_empty(self.config.get(section, key))
+   }
+
+   fn boolean(&mut self, section : &str, key : &str, default : bool) -> bool {
+      match self.optional(section, key) {
+         Some(value) => match value.trim().to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => true,
+            "false" | "no" | "off" | "0" => false,
+            _ => {
+               self.issue(section, key, &format!("has invalid value '{}'; expected true or false", value));
+               default
+            }
+         },
+         None => default,
+      }
+   }
+
+   fn seconds(&mut self, section : &str, key : &str, default : u64) -> u64 {
+      match self.optional(section, key) {
+         Some(value) => match value.trim().parse::<u64>() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+               self.issue(section, key, &format!("has invalid value '{}'; expected a number of seconds", value));
+               default
+            }
+         },
+         None => default,
+      }
+   }
+
+   fn required(&mut self, section : &str, key : &str) -> String {
+      let issue_count = self.issues.len();
+      match self.optional(section, key) {
+         Some(value) => value,
+         None => {
+            // An unreadable secret file has already been reported
+            if self.issues.len() == issue_count {
+               self.issue(section, key, "is missing");
+            }
+            String::new()
+         }
+      }
+   }
+}
+
+fn read_backend(reader : &mut Reader,
+                command_line_backend : Option<Backend>) -> Option<Backend> {
+   let has_sqlite3 = reader.has_section(SQLITE3_SECTION);
+   let has_postgres = reader.has_section(POSTGRES_SECTION);
+   let ini_backend = match reader.optional(POLLER_SECTION, "backend") {
+      Some(value) => match value.parse::<Backend>() {
+         Ok(backend) => Some(backend),
+         Err(_) => {
+            reader.issue(POLLER_SECTION, "backend",
+                         &format!("has invalid value '{}'; expected sqlite3 or postgres", value));
+            return None;
+         }
+      },
+      None => None,
+   };
+   // The command line wins, then the ini file, then whichever database section is present
+   let backend = match command_line_backend.or(ini_backend) {
+      Some(backend) => backend,
+      None => {
+         if has_postgres && !has_sqlite3 {
+            Backend::Postgres
+         }
+         else if has_sqlite3 {
+            Backend::Sqlite3
+         }
+         else {
+            reader.issue(POLLER_SECTION, "backend",
+                         &format!("cannot be inferred; add a [{}] or [{}] section", SQLITE3_SECTION, POSTGRES_SECTION));
+            return None;
+         }
+      }
+   };
+   if backend == Backend::Sqlite3 && !has_sqlite3 {
+      reader.issue(SQLITE3_SECTION, "", "section is missing but the sqlite3 backend was selected");
+   }
+   if backend == Backend::Postgres && !has_postgres {
+      reader.issue(POSTGRES_SECTION, "", "section is missing but the postgres backend was selected");
+   }
+   Some(backend)
+}
+
+fn read_postgres(reader : &mut Reader) -> PostgresParameters {
+   let section = POSTGRES_SECTION;
+   let host = reader.required(section, "host");
+   let port = match reader.optional(section, "port") {
+      Some(value) => match value.trim().parse::<u16>() {
+         Ok(port) if port > 0 => port,
+         _ => {
+            reader.issue(section, "port", &format!("has invalid value '{}'; expected a port number", value));
+            0
+         }
+      },
+      None => 5432,
+   };
+   let name = reader.required(section, "name");
+   let schema = reader.optional(section, "schema").unwrap_or_default();
+   let user = reader.required(section, "user");
+   let password = reader.required(section, "password");
+   let ssl_mode = match reader.optional(section, "sslmode") {
+      Some(value) => match value.parse::<SslMode>() {
+         Ok(ssl_mode) => ssl_mode,
+         Err(_) => {
+            reader.issue(section, "sslmode",
+                         &format!("has invalid value '{}'; expected disable, prefer, require, verify-ca, or verify-full", value));
+            SslMode::Prefer
+         }
+      },
+      None => SslMode::Prefer,
+   };
+   let mut ssl_root_certificate = reader.optional(section, "sslrootcert");
+   let mut ssl_certificate = reader.optional(section, "sslcert");
+   let mut ssl_key = reader.optional(section, "sslkey");
+   for (key, file_name) in [("sslrootcert", &mut ssl_root_certificate),
+                            ("sslcert", &mut ssl_certificate),
+                            ("sslkey", &mut ssl_key)] {
+      if let Some(name) = file_name
+         && !std::path::Path::new(name.as_str()).is_file() {
+         reader.issue(section, key, &format!("names {} which does not exist", name));
+         *file_name = None;
+      }
+   }
+   if ssl_certificate.is_some() != ssl_key.is_some() {
+      reader.issue(section, if ssl_certificate.is_some() { "sslkey" } else { "sslcert" },
+                   "is missing; sslcert and sslkey must be given together");
+   }
+   PostgresParameters {host, port, name, schema, user, password,
+                       ssl_mode, ssl_root_certificate, ssl_certificate, ssl_key}
+}
+
+fn read_api(reader : &mut Reader) -> ApiParameters {
+   let section = API_SECTION;
+   let uri = reader.required(section, "uri");
+   if !uri.is_empty() && !uri.starts_with("https://") && !uri.starts_with("http://") {
+      reader.issue(section, "uri", &format!("has invalid value '{}'; expected an http(s) URL", uri));
+   }
+   let key = reader.required(section, "key");
+   let notification_topic = reader.optional(section, "notificationTopic")
+                                  .unwrap_or(String::from("production"));
+   let notification_type = reader.optional(section, "notificationType")
+                                 .unwrap_or(String::from("update_email"));
+   ApiParameters {uri, key, notification_topic, notification_type}
+}
+
+fn read_notification(reader : &mut Reader,
+                     section : &str,
+                     default : &NotificationParameters) -> NotificationParameters {
+   let subject_template = reader.optional(section, "subject_template").or(default.subject_template.clone());
+   let body_template = reader.optional(section, "body_template");
+   let format = match reader.optional(section, "format") {
+      Some(value) => match value.parse::<NotificationFormat>() {
+         Ok(format) => format,
+         Err(_) => {
+            reader.issue(section, "format", &format!("has invalid value '{}'; expected text, markdown, or html", value));
+            NotificationFormat::Text
+         }
+      },
+      // Otherwise follow the body template's extension
+      None => match body_template.as_deref().and_then(|file| std::path::Path::new(file).extension()) {
+         Some(extension) if extension == "html" || extension == "htm" => NotificationFormat::Html,
+         Some(extension) if extension == "md" => NotificationFormat::Markdown,
+         Some(_) => NotificationFormat::Text,
+         None => default.format,
+      },
+   };
+   // Another format's template would render, e.g., Markdown as HTML, so
+   // fall back to the built-in template for this format instead
+   let body_template = body_template.or_else(|| {
+      default.body_template.clone().filter(|_| format == default.format)
+   });
+   let parameters = NotificationParameters {format, subject_template, body_template};
+   if let Err(error) = crate::notifier::template::Templates::load(&parameters) {
+      let key = if parameters.body_template.is_some() { "body_template" } else { "subject_template" };
+      reader.issue(section, key, &format!("is not a valid template: {}", error));
+   }
+   parameters
+}
+
+fn list(value : Option<String>) -> Vec<String> {
+   value.unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Reads a list of station patterns, e.g., US.BOZ or NN.*.
+fn station_patterns(reader : &mut Reader, section : &str, key : &str) -> Vec<String> {
+   let mut stations = list(reader.optional(section, key));
+   for station in stations.iter_mut() {
+      // Accept UU.ALP or UU_ALP
+      *station = station.replacen('_', ".", 1);
+      if station.split('.').count() != 2 {
+         reader.issue(section, key, &format!("has invalid pattern '{}'; expected network.station, e.g., US.BOZ or NN.*", station));
+      }
+   }
+   stations
+}
+
+fn read_routes(reader : &mut Reader, notification : &NotificationParameters) -> Vec<RouteParameters> {
+   let mut routes : Vec<RouteParameters> = Vec::new();
+   // The ini parser lower-cases section names
+   let prefix = ROUTE_SECTION_PREFIX.to_lowercase();
+   let mut sections : Vec<String> = reader.config.sections().into_iter().filter(|e| e.starts_with(&prefix)).collect();
+   sections.sort();
+   for section in sections.iter() {
+      let name = section[prefix.len()..].to_string();
+      let networks = list(reader.optional(section, "networks"));
+      let stations = station_patterns(reader, section, "stations");
+      if networks.is_empty() && stations.is_empty() {
+         reader.issue(section, "", "section routes nothing; add networks or stations");
+      }
+      let uri = reader.optional(section, "uri");
+      if let Some(uri) = &uri
+         && !uri.starts_with("https://") && !uri.starts_with("http://") {
+         reader.issue(section, "uri", &format!("has invalid value '{}'; expected an http(s) URL", uri));
+      }
+      let key = reader.optional(section, "key");
+      let notification_topic = reader.optional(section, "notificationTopic");
+      let notification_type = reader.optional(section, "notificationType");
+      let notification = read_notification(reader, section, notification);
+      routes.push(RouteParameters {name, networks, stations, uri, key,
+                                   notification_topic, notification_type, notification});
+   }
+   routes
+}
+
+fn read_digest(reader : &mut Reader) -> Option<DigestParameters> {
+   let section = DIGEST_SECTION;
+   if !reader.has_section(section) {
+      return None;
+   }
+   let window = reader.seconds(section, "window", 3600);
+   let max_changes = match reader.optional(section, "max_changes") {
+      Some(value) => match value.trim().parse::<u64>() {
+         Ok(max_changes) => max_changes,
+         Err(_) => {
+            reader.issue(section, "max_changes", &format!("has invalid value '{}'; expected a number", value));
+            0
+         }
+      },
+      None => 0,
+   };
+   if window == 0 && max_changes == 0 {
+      reader.issue(section, "", "section never sends a digest; set window or max_changes");
+   }
+   let critical_stations = station_patterns(reader, section, "critical_stations");
+   Some(DigestParameters {window, max_changes, critical_stations})
+}
+
+fn read_storm(reader : &mut Reader) -> Option<StormParameters> {
+   let section = STORM_SECTION;
+   if !reader.has_section(section) {
+      return None;
+   }
+   let fraction = match reader.optional(section, "fraction") {
+      Some(value) => match value.trim().parse::<f64>() {
+         Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => fraction,
+         _ => {
+            reader.issue(section, "fraction", &format!("has invalid value '{}'; expected a number greater than 0 and at most 1", value));
+            0.5
+         }
+      },
+      None => 0.5,
+   };
+   let min_stations = match reader.optional(section, "min_stations") {
+      Some(value) => match value.trim().parse::<u64>() {
+         Ok(min_stations) => min_stations,
+         Err(_) => {
+            reader.issue(section, "min_stations", &format!("has invalid value '{}'; expected a number", value));
+            10
+         }
+      },
+      None => 10,
+   };
+   Some(StormParameters {fraction, min_stations})
+}
+
+fn read_quiet_hours(reader : &mut Reader) -> Option<QuietHoursParameters> {
+   let section = QUIET_HOURS_SECTION;
+   if !reader.has_section(section) {
+      return None;
+   }
+   let mut time = |key : &str, default : &str| {
+      let value = reader.optional(section, key).unwrap_or(default.to_string());
+      chrono::NaiveTime::parse_from_str(value.trim(), "%H:%M").unwrap_or_else(|_| {
+         reader.issue(section, key, &format!("has invalid value '{}'; expected a time of day, e.g., 22:00", value));
+         chrono::NaiveTime::MIN
+      })
+   };
+   let start = time("start", "22:00");
+   let end = time("end", "07:00");
+   if start == end {
+      reader.issue(section, "end", "is the same as start");
+   }
+   let timezone = match reader.optional(section, "timezone") {
+      Some(value) => value.trim().parse::<chrono_tz::Tz>().unwrap_or_else(|_| {
+         reader.issue(section, "timezone", &format!("has invalid value '{}'; expected a time zone, e.g., America/Denver", value));
+         chrono_tz::UTC
+      }),
+      None => chrono_tz::UTC,
+   };
+   let critical_stations = station_patterns(reader, section, "critical_stations");
+   Some(QuietHoursParameters {start, end, timezone, critical_stations})
+}
+
+fn read_maintenance(reader : &mut Reader) -> Vec<MaintenanceWindow> {
+   let mut windows : Vec<MaintenanceWindow> = Vec::new();
+   for value in list(reader.optional(MAINTENANCE_SECTION, "windows")) {
+      match MaintenanceWindow::parse(&value) {
+         Ok(window) => windows.push(window),
+         Err(error) => reader.issue(MAINTENANCE_SECTION, "windows", &format!("has invalid value: {}", error)),
+      }
+   }
+   windows
+}
+
+fn redacted_notification(notification : &NotificationParameters) -> String {
+   let format = <NotificationFormat as clap::ValueEnum>::to_possible_value(&notification.format)
+                   .map(|value| value.get_name().to_string())
+                   .unwrap_or_default();
+   let mut result = format!("format = {}\n", format);
+   for (key, file_name) in [("subject_template", &notification.subject_template),
+                            ("body_template", &notification.body_template)] {
+      if let Some(file_name) = file_name {
+         result.push_str(&format!("{} = {}\n", key, file_name));
+      }
+   }
+   result
+}
+
+impl Configuration {
+   /// Loads and validates the ini file.  Every problem is reported at once.
+   pub fn load(configuration_file : &str,
+               command_line_backend : Option<Backend>,
+               api_section : ApiSection) -> Result<Configuration, ConfigurationError> {
+      let mut config = configparser::ini::Ini::new();
+      if configuration_file == DEFAULT_INI_FILE && !std::path::Path::new(configuration_file).exists() {
+         log::info!("{} does not exist; reading settings from the environment", configuration_file);
+      }
+      else if let Err(error) = config.load(configuration_file) {
+         let issue = ConfigurationIssue {file: configuration_file.to_string(),
+                                         section: String::new(),
+                                         key: String::new(),
+                                         problem: format!("cannot be read: {}", error)};
+         return Err(ConfigurationError {issues: vec![issue]});
+      }
+      let environment = |variable : &str| std::env::var(variable).ok();
+      Configuration::from_ini(&config, &environment, configuration_file, command_line_backend, api_section)
+   }
+
+   fn from_ini(config : &configparser::ini::Ini,
+               environment : &dyn Fn(&str) -> Option<String>,
+               configuration_file : &str,
+               command_line_backend : Option<Backend>,
+               api_section : ApiSection) -> Result<Configuration, ConfigurationError> {
+      let mut reader = Reader {config, environment, file: configuration_file, issues: Vec::new()};
+      let backend = read_backend(&mut reader, command_line_backend);
+      let auto_migrate = reader.boolean(POLLER_SECTION, "auto_migrate", true);
+      let create_missing = reader.boolean(POLLER_SECTION, "create_missing", false);
+      let lock_timeout = reader.seconds(POLLER_SECTION, "lock_timeout", 0);
+      let poll_interval = reader.seconds(POLLER_SECTION, "poll_interval", 600);
+      if poll_interval == 0 {
+         reader.issue(POLLER_SECTION, "poll_interval", "must be at least one second");
+      }
+      let metrics_address = reader.optional(POLLER_SECTION, "metrics_address");
+      let ready_intervals = match reader.optional(POLLER_SECTION, "ready_intervals") {
+         Some(value) => match value.trim().parse::<u64>() {
+            Ok(intervals) if intervals > 0 => intervals,
+            _ => {
+               reader.issue(POLLER_SECTION, "ready_intervals",
+                            &format!("has invalid value '{}'; expected a positive number", value));
+               3
+            }
+         },
+         None => 3,
+      };
+      if let Some(address) = &metrics_address
+         && std::net::ToSocketAddrs::to_socket_addrs(address.as_str()).is_err() {
+         reader.issue(POLLER_SECTION, "metrics_address",
+                      &format!("has invalid value '{}'; expected host:port", address));
+      }
+      let mut sqlite3 = None;
+      let mut postgres = None;
+      match backend {
+         Some(Backend::Sqlite3) => {
+            let file_name = reader.optional(SQLITE3_SECTION, "file_name")
+                                  .unwrap_or(String::from("./sisPoller.sqlite3"));
+            sqlite3 = Some(Sqlite3Parameters {file_name});
+         }
+         Some(Backend::Postgres) if reader.has_section(POSTGRES_SECTION) => {
+            postgres = Some(read_postgres(&mut reader));
+         }
+         _ => {}
+      }
+      let mut api = None;
+      if api_section == ApiSection::Required
+         || (api_section == ApiSection::IfPresent && reader.has_section(API_SECTION)) {
+         api = Some(read_api(&mut reader));
+      }
+      let default_notification = NotificationParameters {format: NotificationFormat::Text,
+                                                          subject_template: None,
+                                                          body_template: None};
+      let notification = read_notification(&mut reader, NOTIFICATION_SECTION, &default_notification);
+      let routes = read_routes(&mut reader, &notification);
+      let digest = read_digest(&mut reader);
+      let storm = read_storm(&mut reader);
+      let quiet_hours = read_quiet_hours(&mut reader);
+      let maintenance = read_maintenance(&mut reader);
+      if !reader.issues.is_empty() {
+         return Err(ConfigurationError {issues: reader.issues});
+      }
+      Ok(Configuration {file: configuration_file.to_string(),
+                        backend: backend.unwrap_or(Backend::Sqlite3),
+                        auto_migrate,
+                        create_missing,
+                        lock_timeout,
+                        poll_interval,
+                        metrics_address,
+                        ready_intervals,
+                        sqlite3,
+                        postgres,
+                        api,
+                        notification,
+                        routes,
+                        digest,
+                        storm,
+                        quiet_hours,
+                        maintenance})
+   }
+
+   /// The effective settings with passwords and keys redacted.
+   pub fn redacted(&self) -> String {
+      let mut result = format!("# Effective settings from {}\n", self.file);
+      result.push_str(&format!("[{}]\nbackend = {}\nauto_migrate = {}\ncreate_missing = {}\nlock_timeout = {}\npoll_interval = {}\n",
+                               POLLER_SECTION, format!("{:?}", self.backend).to_lowercase(),
+                               self.auto_migrate, self.create_missing, self.lock_timeout, self.poll_interval));
+      if let Some(metrics_address) = &self.metrics_address {
+         result.push_str(&format!("metrics_address = {}\n", metrics_address));
+      }
+      result.push_str(&format!("ready_intervals = {}\n", self.ready_intervals));
+      if let Some(sqlite3) = &self.sqlite3 {
+         result.push_str(&format!("\n[{}]\nfile_name = {}\n", SQLITE3_SECTION, sqlite3.file_name));
+      }
+      if let Some(postgres) = &self.postgres {
+         result.push_str(&format!("\n[{}]\nhost = {}\nport = {}\nname = {}\nschema = {}\nuser = {}\npassword = {}\n",
+                                  POSTGRES_SECTION, postgres.host, postgres.port, postgres.name,
+                                  postgres.schema, postgres.user, REDACTED));
+         let ssl_mode = <SslMode as clap::ValueEnum>::to_possible_value(&postgres.ssl_mode)
+                           .map(|value| value.get_name().to_string())
+                           .unwrap_or_default();
+         result.push_str(&format!("sslmode = {}\n", ssl_mode));
+         for (key, file_name) in [("sslrootcert", &postgres.ssl_root_certificate),
+                                  ("sslcert", &postgres.ssl_certificate),
+                                  ("sslkey", &postgres.ssl_key)] {
+            if let Some(file_name) = file_name {
+               result.push_str(&format!("{} = {}\n", key, file_name));
+            }
+         }
+      }
+      if let Some(api) = &self.api {
+         result.push_str(&format!("\n[{}]\nuri = {}\nkey = {}\nnotificationTopic = {}\nnotificationType = {}\n",
+                                  API_SECTION, api.uri, REDACTED,
+                                  api.notification_topic, api.notification_type));
+      }
+      result.push_str(&format!("\n[{}]\n{}", NOTIFICATION_SECTION, redacted_notification(&self.notification)));
+      for route in self.routes.iter() {
+         result.push_str(&format!("\n[{}{}]\n", ROUTE_SECTION_PREFIX, route.name));
+         if !route.networks.is_empty() {
+            result.push_str(&format!("networks = {}\n", route.networks.join(", ")));
+         }
+         if !route.stations.is_empty() {
+            result.push_str(&format!("stations = {}\n", route.stations.join(", ")));
+         }
+         for (key, value) in [("uri", &route.uri),
+                              ("key", &route.key.as_ref().map(|_| REDACTED.to_string())),
+                              ("notificationTopic", &route.notification_topic),
+                              ("notificationType", &route.notification_type)] {
+            if let Some(value) = value {
+               result.push_str(&format!("{} = {}\n", key, value));
+            }
+         }
+         result.push_str(&redacted_notification(&route.notification));
+      }
+      if let Some(digest) = &self.digest {
+         result.push_str(&format!("\n[{}]\nwindow = {}\nmax_changes = {}\n",
+                                  DIGEST_SECTION, digest.window, digest.max_changes));
+         if !digest.critical_stations.is_empty() {
+            result.push_str(&format!("critical_stations = {}\n", digest.critical_stations.join(", ")));
+         }
+      }
+      if let Some(storm) = &self.storm {
+         result.push_str(&format!("\n[{}]\nfraction = {}\nmin_stations = {}\n",
+                                  STORM_SECTION, storm.fraction, storm.min_stations));
+      }
+      if let Some(quiet_hours) = &self.quiet_hours {
+         result.push_str(&format!("\n[{}]\nstart = {}\nend = {}\ntimezone = {}\n",
+                                  QUIET_HOURS_SECTION, quiet_hours.start.format("%H:%M"),
+                                  quiet_hours.end.format("%H:%M"), quiet_hours.timezone));
+         if !quiet_hours.critical_stations.is_empty() {
+            result.push_str(&format!("critical_stations = {}\n", quiet_hours.critical_stations.join(", ")));
+         }
+      }
+      if !self.maintenance.is_empty() {
+         let windows : Vec<String> = self.maintenance.iter().map(|window| window.to_string()).collect();
+         result.push_str(&format!("\n[{}]\nwindows = {}\n", MAINTENANCE_SECTION, windows.join(", ")));
+      }
+      result
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn from_string(text : &str,
+                  backend : Option<Backend>,
+                  require_api : bool) -> Result<Configuration, ConfigurationError> {
+      from_string_and_environment(text, &[], backend, require_api)
+   }
+
+   fn from_string_and_environment(text : &str,
+                                  variables : &[(&str, &str)],
+                                  backend : Option<Backend>,
+                                  require_api : bool) -> Result<Configuration, ConfigurationError> {
+      let mut config = configparser::ini::Ini::new();
+      config.read(text.to_string()).unwrap();
+      let environment = |variable : &str| {
+         variables.iter().find(|(name, _)| *name == variable).map(|(_, value)| value.to_string())
+      };
+      let api_section = if require_api { ApiSection::Required } else { ApiSection::Ignored };
+      Configuration::from_ini(&config, &environment, "test.ini", backend, api_section)
+   }
+
+   #[test]
+   fn test_select_backend() {
+      let text = "[SISSqlite3Database]\nfile_name = a.sqlite3\n";
+      assert_eq!(from_string(text, None, false).unwrap().backend, Backend::Sqlite3);
+      assert!(from_string(text, Some(Backend::Postgres), false).is_err());
+
+      let text = "[SISPoller]\nbackend = Postgres\n[SISPostgresDatabase]\nhost = localhost\nname = sis\nuser = u\npassword = p\n[SISSqlite3Database]\n";
+      assert_eq!(from_string(text, None, false).unwrap().backend, Backend::Postgres);
+      assert_eq!(from_string(text, Some(Backend::Sqlite3), false).unwrap().backend, Backend::Sqlite3);
+
+      let text = "[SISPoller]\nbackend = mysql\n[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).is_err());
+   }
+
+   #[test]
+   fn test_poller_options() {
+      let text = "[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).unwrap().auto_migrate);
+      assert!(!from_string(text, None, false).unwrap().create_missing);
+      assert_eq!(from_string(text, None, false).unwrap().lock_timeout, 0);
+      let text = "[SISPoller]\nlock_timeout = 30\n[SISSqlite3Database]\n";
+      assert_eq!(from_string(text, None, false).unwrap().lock_timeout, 30);
+      let text = "[SISPoller]\nlock_timeout = soon\n[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).is_err());
+      let text = "[SISPoller]\npoll_interval = 0\n[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).is_err());
+      let text = "[SISPoller]\nmetrics_address = 127.0.0.1:9184\n[SISSqlite3Database]\n";
+      assert_eq!(from_string(text, None, false).unwrap().metrics_address.as_deref(), Some("127.0.0.1:9184"));
+      let text = "[SISPoller]\nmetrics_address = 9184\n[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).is_err());
+      let text = "[SISPoller]\nauto_migrate = no\n[SISSqlite3Database]\n";
+      assert!(!from_string(text, None, false).unwrap().auto_migrate);
+      let text = "[SISPoller]\nauto_migrate = sometimes\n[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).is_err());
+   }
+
+   #[test]
+   fn test_notification_options() {
+      let text = "[SISSqlite3Database]\n";
+      assert_eq!(from_string(text, None, false).unwrap().notification.format, NotificationFormat::Text);
+      let text = "[SISSqlite3Database]\n[Notification]\nformat = HTML\n";
+      assert_eq!(from_string(text, None, false).unwrap().notification.format, NotificationFormat::Html);
+      let text = "[SISSqlite3Database]\n[Notification]\nformat = pdf\n";
+      assert!(from_string(text, None, false).is_err());
+
+      let directory = std::env::temp_dir().join(format!("sis_poller_templates_{}", std::process::id()));
+      std::fs::create_dir_all(&directory).unwrap();
+      let body = directory.join("body.md");
+      std::fs::write(&body, "{% for network in networks %}{{ network.name }}{% endfor %}").unwrap();
+      let text = format!("[SISSqlite3Database]\n[Notification]\nbody_template = {}\n", body.display());
+      assert_eq!(from_string(&text, None, false).unwrap().notification.format, NotificationFormat::Markdown);
+      std::fs::write(&body, "{% for network in networks %}").unwrap();
+      let error = from_string(&text, None, false).unwrap_err();
+      assert_eq!(error.issues[0].key, "body_template");
+      std::fs::remove_dir_all(&directory).unwrap();
+      let error = from_string(&text, None, false).unwrap_err();
+      assert!(error.to_string().contains("Failed to read template"));
+   }
+
+   #[test]
+   fn test_routes() {
+      let text = "[SISSqlite3Database]\n[Notification]\nformat = markdown\n\
+                  [Route.keepers]\nnetworks = IW\nstations = US_BOZ, NN.*\nnotificationTopic = keepers\nkey = secret\n\
+                  [Route.metadata]\nnetworks = UU\nformat = html\n";
+      let configuration = from_string(text, None, false).unwrap();
+      assert_eq!(configuration.routes.len(), 2);
+      let keepers = &configuration.routes[0];
+      assert_eq!(keepers.name, "keepers");
+      assert_eq!(keepers.stations, vec!["US.BOZ", "NN.*"]);
+      assert_eq!(keepers.notification_topic.as_deref(), Some("keepers"));
+      assert_eq!(keepers.notification.format, NotificationFormat::Markdown);
+      assert_eq!(configuration.routes[1].notification.format, NotificationFormat::Html);
+      assert!(!configuration.redacted().contains("secret"));
+
+      let text = "[SISSqlite3Database]\n[Route.empty]\nnotificationTopic = nobody\n[Route.bad]\nstations = BOZ\n";
+      let error = from_string(text, None, false).unwrap_err();
+      let sections : Vec<&str> = error.issues.iter().map(|issue| issue.section.as_str()).collect();
+      assert_eq!(sections, vec!["route.bad", "route.empty"]);
+   }
+
+   #[test]
+   fn test_route_body_template() {
+      let body = std::env::temp_dir().join(format!("sis_poller_body_{}.md", std::process::id()));
+      std::fs::write(&body, "{{ subject }}\n").unwrap();
+      let text = format!("[SISSqlite3Database]\n[Notification]\nbody_template = {}\n\
+                          [Route.markdown]\nnetworks = IW\n[Route.html]\nnetworks = UU\nformat = html\n",
+                         body.display());
+      let configuration = from_string(&text, None, false).unwrap();
+      std::fs::remove_file(&body).unwrap();
+      assert_eq!(configuration.notification.format, NotificationFormat::Markdown);
+      let html = &configuration.routes[0].notification;
+      assert_eq!(html.format, NotificationFormat::Html);
+      assert!(html.body_template.is_none());
+      let markdown = &configuration.routes[1].notification;
+      assert_eq!(markdown.format, NotificationFormat::Markdown);
+      assert_eq!(markdown.body_template.as_deref(), body.to_str());
+   }
+
+   #[test]
+   fn test_digest() {
+      let text = "[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).unwrap().digest.is_none());
+      let text = "[SISSqlite3Database]\n[Digest]\ncritical_stations = US_BOZ, NN.*\n";
+      let digest = from_string(text, None, false).unwrap().digest.unwrap();
+      assert_eq!(digest.window, 3600);
+      assert_eq!(digest.max_changes, 0);
+      assert_eq!(digest.critical_stations, vec!["US.BOZ", "NN.*"]);
+      let variables = [("SIS_POLLER_DIGEST_MAX_CHANGES", "25")];
+      let digest = from_string_and_environment("[SISSqlite3Database]\n", &variables, None, false).unwrap().digest.unwrap();
+      assert_eq!(digest.max_changes, 25);
+      let text = "[SISSqlite3Database]\n[Digest]\nwindow = 0\n";
+      assert!(from_string(text, None, false).is_err());
+      let text = "[SISSqlite3Database]\n[Digest]\nmax_changes = many\ncritical_stations = BOZ\n";
+      let error = from_string(text, None, false).unwrap_err();
+      let keys : Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+      assert_eq!(keys, vec!["max_changes", "critical_stations"]);
+   }
+
+   #[test]
+   fn test_storm() {
+      let text = "[SISSqlite3Database]\n";
+      assert!(from_string(text, None, false).unwrap().storm.is_none());
+      let text = "[SISSqlite3Database]\n[Storm]\n";
+      let storm = from_string(text, None, false).unwrap().storm.unwrap();
+      assert_eq!(storm.fraction, 0.5);
+      assert_eq!(storm.min_stations, 10);
+      let text = "[SISSqlite3Database]\n[Storm]\nfraction = 0.8\nmin_stations = 3\n";
+      let storm = from_string(text, None, false).unwrap().storm.unwrap();
+      assert_eq!((storm.fraction, storm.min_stations), (0.8, 3));
+      for fraction in ["0", "1.5", "half"] {
+         let text = format!("[SISSqlite3Database]\n[Storm]\nfraction = {}\n", fraction);
+         assert!(from_string(&text, None, false).is_err());
+      }
+   }
+
+   #[test]
+   fn test_quiet_hours() {
+      let text = "[SISSqlite3Database]\n[QuietHours]\ntimezone = America/Denver\ncritical_stations = US.BOZ\n";
+      let quiet_hours = from_string(text, None, false).unwrap().quiet_hours.unwrap();
+      // 2023-05-30 09:29 UTC is 03:29 in Denver
+      let time = crate::parser::parse_string("2023-05-30 09:29").unwrap();
+      assert!(quiet_hours.contains(time));
+      assert!(!quiet_hours.contains(time + chrono::Duration::hours(4)));
+      let text = "[SISSqlite3Database]\n[QuietHours]\nstart = 01:00\nend = 04:00\n";
+      let quiet_hours = from_string(text, None, false).unwrap().quiet_hours.unwrap();
+      assert!(!quiet_hours.contains(time));
+      assert!(quiet_hours.contains(time - chrono::Duration::hours(6)));
+      let text = "[SISSqlite3Database]\n[QuietHours]\nstart = 10pm\ntimezone = Mars/Olympus\n";
+      let error = from_string(text, None, false).unwrap_err();
+      let keys : Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+      assert_eq!(keys, vec!["start", "timezone"]);
+
+      let text = "[SISSqlite3Database]\n[Maintenance]\nwindows = 2023-05-30 09:00/2023-05-30 12:00, 2023-06-01 00:00/2023-06-02 00:00\n";
+      let maintenance = from_string(text, None, false).unwrap().maintenance;
+      assert_eq!(maintenance.len(), 2);
+      assert!(maintenance[0].contains(time));
+      let text = "[SISSqlite3Database]\n[Maintenance]\nwindows = 2023-05-30 09:00\n";
+      assert!(from_string(text, None, false).is_err());
+   }
+
+   #[test]
+   fn test_every_issue_is_reported() {
+      let text = "[SISPostgresDatabase]\nhost = localhost\nport = abc\n[AWSDistributionAPI]\nuri = ftp://example\n";
+      let error = from_string(text, None, true).unwrap_err();
+      let keys : Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+      assert_eq!(keys, vec!["port", "name", "user", "password", "uri", "key"]);
+      assert!(error.to_string().contains("[SISPostgresDatabase] port"));
+   }
+
+   #[test]
+   fn test_api_section_if_present() {
+      let from_ini = |text : &str| {
+         let mut config = configparser::ini::Ini::new();
+         config.read(text.to_string()).unwrap();
+         Configuration::from_ini(&config, &|_ : &str| None, "test.ini", None, ApiSection::IfPresent)
+      };
+      // A sqlite3 configuration without the section is valid for everything but posting
+      assert!(from_ini("[SISSqlite3Database]\n").unwrap().api.is_none());
+      let text = "[SISSqlite3Database]\n[AWSDistributionAPI]\nuri = https://example\nkey = k\n";
+      assert_eq!(from_ini(text).unwrap().api.unwrap().uri, "https://example");
+      let error = from_ini("[SISSqlite3Database]\n[AWSDistributionAPI]\nuri = ftp://example\n").unwrap_err();
+      let keys : Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+      assert_eq!(keys, vec!["uri", "key"]);
+   }
+
+   #[test]
+   fn test_redacted() {
+      let text = "[SISPostgresDatabase]\nhost = localhost\nname = sis\nuser = u\npassword = hunter2\n[AWSDistributionAPI]\nuri = https://example\nkey = secret\n";
+      let redacted = from_string(text, None, true).unwrap().redacted();
+      assert!(!redacted.contains("hunter2"));
+      assert!(!redacted.contains("secret"));
+      assert!(redacted.contains("port = 5432"));
+      assert!(redacted.contains("sslmode = prefer"));
+   }
+
+   #[test]
+   fn test_environment_overrides() {
+      let directory = std::env::temp_dir().join(format!("sis_poller_secret_{}", std::process::id()));
+      std::fs::create_dir_all(&directory).unwrap();
+      let ini_secret = directory.join("ini_password");
+      let environment_secret = directory.join("environment_password");
+      std::fs::write(&ini_secret, "from_ini_file\n").unwrap();
+      std::fs::write(&environment_secret, "from_environment_file\n").unwrap();
+
+      let text = format!("[SISPostgresDatabase]\nhost = ini_host\nname = sis\nuser = u\npassword = from_ini\npassword_file = {}\n",
+                         ini_secret.display());
+      // The secret file in the ini file beats the plain ini value
+      let configuration = from_string_and_environment(&text, &[], None, false).unwrap();
+      assert_eq!(configuration.postgres.as_ref().unwrap().password, "from_ini_file");
+      assert_eq!(configuration.postgres.as_ref().unwrap().host, "ini_host");
+
+      // The environment's secret file beats the ini file
+      let environment_secret_name = environment_secret.display().to_string();
+      let variables = [("SIS_POLLER_DATABASE_PASSWORD_FILE", environment_secret_name.as_str()),
+                       ("SIS_POLLER_DATABASE_HOST", "environment_host")];
+      let configuration = from_string_and_environment(&text, &variables, None, false).unwrap();
+      assert_eq!(configuration.postgres.as_ref().unwrap().password, "from_environment_file");
+      assert_eq!(configuration.postgres.as_ref().unwrap().host, "environment_host");
+
+      // The environment variable beats everything but the command line
+      let variables = [("SIS_POLLER_DATABASE_PASSWORD_FILE", environment_secret_name.as_str()),
+                       ("SIS_POLLER_DATABASE_PASSWORD", "from_environment"),
+                       ("SIS_POLLER_BACKEND", "sqlite3")];
+      let configuration = from_string_and_environment(&text, &variables, Some(Backend::Postgres), false).unwrap();
+      assert_eq!(configuration.postgres.as_ref().unwrap().password, "from_environment");
+
+      // A section can come entirely from the environment
+      let variables = [("SIS_NOTIFICATION_API_URI", "https://example"), ("SIS_NOTIFICATION_API_KEY", "k")];
+      let configuration = from_string_and_environment("[SISSqlite3Database]\n", &variables, None, true).unwrap();
+      assert_eq!(configuration.api.unwrap().uri, "https://example");
+
+      // An unreadable secret file is reported against its key
+      let variables = [("SIS_NOTIFICATION_API_KEY_FILE", "/nonexistent/key")];
+      let text = "[SISSqlite3Database]\n[AWSDistributionAPI]\nuri = https://example\n";
+      let error = from_string_and_environment(text, &variables, None, true).unwrap_err();
+      assert!(error.to_string().contains("SIS_NOTIFICATION_API_KEY_FILE"));
+      std::fs::remove_dir_all(&directory).unwrap();
+   }
+
+   #[test]
+   fn test_ssl_options() {
+      let text = "[SISPostgresDatabase]\nhost = h\nname = n\nuser = u\npassword = p\nsslmode = verify-full\n";
+      let configuration = from_string(text, None, false).unwrap();
+      assert_eq!(configuration.postgres.unwrap().ssl_mode, SslMode::VerifyFull);
+
+      let text = "[SISPostgresDatabase]\nhost = h\nname = n\nuser = u\npassword = p\nsslmode = sometimes\nsslcert = /nonexistent/client.pem\n";
+      let error = from_string(text, None, false).unwrap_err();
+      let keys : Vec<&str> = error.issues.iter().map(|issue| issue.key.as_str()).collect();
+      assert_eq!(keys, vec!["sslmode", "sslcert"]);
+   }
+}
@@ -0,0 +1,112 @@
+/// A versioned change to the database schema.  Applied versions are recorded
+/// in the schema_version table so each migration runs exactly once.
+pub struct Migration {
+   pub version : i64,
+   pub description : &'static str,
+   pub sql : &'static str,
+}
+
+/// The schema version that adds the pending_notification table.
+pub static PENDING_VERSION: i64 = 3;
+/// The schema version that adds the maintenance_window table.
+pub static MAINTENANCE_VERSION: i64 = 4;
+
+/// Tables that predate versioning.  These are created if missing before any
+/// migration runs so that both new and existing databases share one history.
+pub static SQLITE3_BASELINE: &str = "
+   CREATE TABLE IF NOT EXISTS xml_update (xml_file TEXT, last_modified TEXT);
+   CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, description TEXT NOT NULL, applied TEXT NOT NULL);
+";
+
+pub static SQLITE3: &[Migration] = &[
+   Migration {
+      version: 1,
+      description: "Add a unique key on xml_update.xml_file",
+      // Keep the most recently modified row for any duplicated file
+      sql: "
+         DELETE FROM xml_update WHERE rowid NOT IN
+            (SELECT rowid FROM (SELECT rowid, MAX(unixepoch(last_modified)) FROM xml_update GROUP BY xml_file));
+         CREATE UNIQUE INDEX IF NOT EXISTS xml_update_xml_file_key ON xml_update (xml_file);
+      ",
+   },
+   Migration {
+      version: 2,
+      description: "Add the xml_update_history table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS xml_update_history (xml_file TEXT, action TEXT, last_modified TEXT, detected TEXT);
+      ",
+   },
+   Migration {
+      version: 3,
+      description: "Add the pending_notification table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS pending_notification (xml_file TEXT PRIMARY KEY, action TEXT NOT NULL, old_modified TEXT, last_modified TEXT NOT NULL, detected TEXT NOT NULL);
+      ",
+   },
+   Migration {
+      version: 4,
+      description: "Add the maintenance_window table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS maintenance_window (starts TEXT NOT NULL, ends TEXT NOT NULL, reason TEXT);
+      ",
+   },
+];
+
+pub static POSTGRES_BASELINE: &str = "
+   CREATE TABLE IF NOT EXISTS xml_update (xml_file TEXT NOT NULL, last_modified TIMESTAMP DEFAULT timezone('UTC'::text, CURRENT_TIMESTAMP));
+   CREATE TABLE IF NOT EXISTS schema_version (version BIGINT PRIMARY KEY, description TEXT NOT NULL, applied TIMESTAMP NOT NULL DEFAULT timezone('UTC'::text, CURRENT_TIMESTAMP));
+";
+
+pub static POSTGRES: &[Migration] = &[
+   Migration {
+      version: 1,
+      description: "Add a unique key on xml_update.xml_file",
+      // Keep the most recently modified row for any duplicated file
+      sql: "
+         DELETE FROM xml_update a USING xml_update b
+            WHERE a.xml_file = b.xml_file
+              AND (a.last_modified < b.last_modified
+                   OR (a.last_modified IS NULL AND b.last_modified IS NOT NULL)
+                   OR (a.last_modified IS NOT DISTINCT FROM b.last_modified AND a.ctid < b.ctid));
+         CREATE UNIQUE INDEX IF NOT EXISTS xml_update_xml_file_key ON xml_update (xml_file);
+      ",
+   },
+   Migration {
+      version: 2,
+      description: "Add the xml_update_history table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS xml_update_history (xml_file TEXT NOT NULL, action TEXT NOT NULL, last_modified TIMESTAMP, detected TIMESTAMP DEFAULT timezone('UTC'::text, CURRENT_TIMESTAMP));
+      ",
+   },
+   Migration {
+      version: 3,
+      description: "Add the pending_notification table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS pending_notification (xml_file TEXT PRIMARY KEY, action TEXT NOT NULL, old_modified TIMESTAMP, last_modified TIMESTAMP NOT NULL, detected TIMESTAMP NOT NULL);
+      ",
+   },
+   Migration {
+      version: 4,
+      description: "Add the maintenance_window table",
+      sql: "
+         CREATE TABLE IF NOT EXISTS maintenance_window (starts TIMESTAMP NOT NULL, ends TIMESTAMP NOT NULL, reason TEXT);
+      ",
+   },
+];
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_versions_are_increasing() {
+      for migrations in [SQLITE3, POSTGRES] {
+         for (index, migration) in migrations.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+         }
+      }
+      assert_eq!(SQLITE3.len(), POSTGRES.len());
+      assert!(SQLITE3[PENDING_VERSION as usize - 1].sql.contains("pending_notification"));
+      assert!(SQLITE3[MAINTENANCE_VERSION as usize - 1].sql.contains("maintenance_window"));
+   }
+}
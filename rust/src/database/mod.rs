@@ -1,2 +1,249 @@
+pub mod migrations;
 pub mod sqlite3;
 pub mod postgres;
+use crate::datatypes::station_time::StationTime;
+use crate::datatypes::station_change::StationChange;
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::maintenance_window::MaintenanceWindow;
+
+/// What an upsert did to each station.
+#[derive(Clone, Debug, Default)]
+pub struct UpsertResult {
+   pub inserted : Vec<StationTime>,
+   pub updated : Vec<StationTime>,
+   pub unchanged : Vec<StationTime>,
+   /// Stations whose statement failed; the error is logged
+   pub failed : Vec<StationTime>,
+}
+
+/// What an update did to each station.  Missing stations had no row to
+/// update, e.g., because it was deleted after the database was read.
+#[derive(Clone, Debug, Default)]
+pub struct UpdateResult {
+   pub updated : Vec<StationTime>,
+   pub missing : Vec<StationTime>,
+   /// Stations whose statement failed; the error is logged
+   pub failed : Vec<StationTime>,
+}
+
+/// What a removal did to each station.  Missing stations had no row to
+/// delete, e.g., because another instance removed it first.
+#[derive(Clone, Debug, Default)]
+pub struct RemoveResult {
+   pub removed : Vec<StationTime>,
+   pub missing : Vec<StationTime>,
+   /// Stations whose statement failed; the error is logged
+   pub failed : Vec<StationTime>,
+}
+
+/// What replacing the stored stations did.
+#[derive(Clone, Debug, Default)]
+pub struct ReplaceResult {
+   pub upserted : UpsertResult,
+   /// Stored stations the replacement did not list
+   pub removed : Vec<StationTime>,
+   pub history_removed : usize,
+   /// Held changes dropped because they no longer match a stored station
+   pub pending_removed : usize,
+}
+
+/// Added and updated stations held for a later notification, e.g., a
+/// digest.  A station held more than once keeps its first old time and
+/// detection time and its latest time.
+#[derive(Clone, Debug, Default)]
+pub struct Pending {
+   pub changes : ChangeSet,
+   /// When the oldest held change was detected, in UTC seconds since the epoch
+   pub since : Option<i64>,
+}
+
+/// Keeps other instances from writing while held; dropping it releases the
+/// lock.
+pub enum RunLock {
+   File { _file : std::fs::File },
+   Postgres { _client : Box<::postgres::Client> },
+}
+
+/// Checks that the store is reachable without touching the connection the
+/// poll uses, e.g., from the health check thread.
+#[derive(Clone)]
+pub enum Probe {
+   Sqlite3(String),
+   Postgres(Box<postgres::Connection>),
+}
+
+impl Probe {
+   pub fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+      match self {
+         Probe::Sqlite3(file) => sqlite3::check(file),
+         Probe::Postgres(connection) => connection.check(),
+      }
+   }
+}
+
+/// The storage backend holding the xml_update table.  Each variant owns its
+/// connection for the lifetime of the run.
+pub enum Database {
+   Sqlite3(sqlite3::Store),
+   Postgres(Box<postgres::Store>),
+}
+
+impl Database {
+   pub fn sqlite3(file : &str, auto_migrate : bool) -> Database {
+      Database::Sqlite3(sqlite3::Store::new(file, auto_migrate))
+   }
+
+   pub fn postgres(connection : postgres::Connection, auto_migrate : bool) -> Database {
+      Database::Postgres(Box::new(postgres::Store::new(connection, auto_migrate)))
+   }
+
+   /// Takes the single-instance run lock, waiting up to timeout.  Returns None
+   /// if another instance still holds it.
+   pub fn lock(&self, timeout : std::time::Duration) -> Result<Option<RunLock>, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => Ok(store.lock(timeout)?.map(|file| RunLock::File {_file: file})),
+         Database::Postgres(store) => Ok(store.lock(timeout)?.map(|client| RunLock::Postgres {_client: Box::new(client)})),
+      }
+   }
+
+   /// Applies any pending migrations and returns the ones that ran.
+   pub fn migrate(&mut self) -> Result<Vec<&'static migrations::Migration>, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.migrate(),
+         Database::Postgres(store) => store.migrate(),
+      }
+   }
+
+   pub fn schema_version(&mut self) -> Result<i64, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.schema_version(),
+         Database::Postgres(store) => store.schema_version(),
+      }
+   }
+
+   pub fn get_stations(&mut self) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.get_stations(),
+         Database::Postgres(store) => store.get_stations(),
+      }
+   }
+
+   pub fn upsert_stations(&mut self,
+                          stations : &[StationTime]) -> Result<UpsertResult, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.upsert_stations(stations),
+         Database::Postgres(store) => store.upsert_stations(stations),
+      }
+   }
+
+   pub fn update_stations(&mut self,
+                          stations_to_update : &[StationTime]) -> Result<UpdateResult, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.update_stations(stations_to_update),
+         Database::Postgres(store) => store.update_stations(stations_to_update),
+      }
+   }
+
+   pub fn remove_stations(&mut self,
+                          stations_to_remove : &[StationTime]) -> Result<RemoveResult, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.remove_stations(stations_to_remove),
+         Database::Postgres(store) => store.remove_stations(stations_to_remove),
+      }
+   }
+
+   /// Replaces the stored stations, and the history if given, in one
+   /// transaction.
+   pub fn replace_stations(&mut self,
+                           stations : &[StationTime],
+                           history : Option<&[StationChange]>) -> Result<ReplaceResult, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.replace_stations(stations, history),
+         Database::Postgres(store) => store.replace_stations(stations, history),
+      }
+   }
+
+   pub fn add_history(&mut self,
+                      changes : &[StationChange]) -> Result<(), Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.add_history(changes),
+         Database::Postgres(store) => store.add_history(changes),
+      }
+   }
+
+   pub fn get_history(&mut self) -> Result<Vec<StationChange>, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.get_history(),
+         Database::Postgres(store) => store.get_history(),
+      }
+   }
+
+   /// Removes every history entry and returns how many there were.
+   pub fn clear_history(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.clear_history(),
+         Database::Postgres(store) => store.clear_history(),
+      }
+   }
+
+   pub fn add_pending(&mut self,
+                      changes : &ChangeSet,
+                      detected : i64) -> Result<(), Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.add_pending(changes, detected),
+         Database::Postgres(store) => store.add_pending(changes, detected),
+      }
+   }
+
+   pub fn get_pending(&mut self) -> Result<Pending, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.get_pending(),
+         Database::Postgres(store) => store.get_pending(),
+      }
+   }
+
+   pub fn remove_pending(&mut self,
+                         stations : &[StationTime]) -> Result<(), Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.remove_pending(stations),
+         Database::Postgres(store) => store.remove_pending(stations),
+      }
+   }
+
+   pub fn add_maintenance(&mut self,
+                          window : &MaintenanceWindow) -> Result<(), Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.add_maintenance(window),
+         Database::Postgres(store) => store.add_maintenance(window),
+      }
+   }
+
+   pub fn get_maintenance(&mut self) -> Result<Vec<MaintenanceWindow>, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.get_maintenance(),
+         Database::Postgres(store) => store.get_maintenance(),
+      }
+   }
+
+   /// Removes every stored maintenance window and returns how many there were.
+   pub fn clear_maintenance(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      match self {
+         Database::Sqlite3(store) => store.clear_maintenance(),
+         Database::Postgres(store) => store.clear_maintenance(),
+      }
+   }
+
+   pub fn probe(&self) -> Probe {
+      match self {
+         Database::Sqlite3(store) => Probe::Sqlite3(store.file().to_string()),
+         Database::Postgres(store) => Probe::Postgres(Box::new(store.connection().clone())),
+      }
+   }
+
+   pub fn name(&self) -> &'static str {
+      match self {
+         Database::Sqlite3(_) => "sqlite3",
+         Database::Postgres(_) => "postgres",
+      }
+   }
+}
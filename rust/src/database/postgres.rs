@@ -1,85 +1,639 @@
 use crate::datatypes::station_time::StationTime;
+use crate::datatypes::station_change::StationChange;
+use crate::configuration::{PostgresParameters, SslMode};
+use crate::database::migrations::{self, Migration};
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::maintenance_window::MaintenanceWindow;
+use crate::database::{Pending, RemoveResult, ReplaceResult, UpdateResult, UpsertResult};
+use std::sync::Arc;
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
 
-#[allow(dead_code)]
-pub fn get_stations(connection_uri : &str, schema : &str) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   let mut stations : Vec<StationTime> = Vec::new();
-   let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-   if !schema.is_empty() {
-      let _ = client.execute("SET search_path TO production", &[])?;
-   }
-   for row in client.query("SELECT xml_file, EXTRACT(epoch FROM last_modified)::bigint AS last_modified FROM xml_update", &[])? {
-      let station : &str = row.get(0);
-      let time : i64 = row.get(1);
-      let pair = StationTime {station: station.to_string(), time: time};
-      log::debug!("Database station {} {}", station, time);
-      stations.push(pair);
-   }
-   return Ok(stations);
+/// How to reach the postgres database.  The parameters are handed to
+/// postgres::Config directly so the password never appears in a URI.
+#[derive(Clone)]
+pub struct Connection {
+   pub config : postgres::Config,
+   pub schema : String,
+   pub tls : Option<tokio_postgres_rustls::MakeRustlsConnect>,
 }
 
-#[allow(dead_code)]
-pub fn create_stations(connection_uri : &str, 
-                       schema : &str,
-                       stations_to_create : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   let mut created_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_create.is_empty() {
-       let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-       if !schema.is_empty() {
-          let _ = client.execute("SET search_path TO production", &[])?;
-       }
-       for station in stations_to_create.iter() {
-          let time : f64 = station.time as f64;
-          let result = client.execute(
-                       "INSERT INTO xml_update (xml_file, last_modified) VALUES($1, TO_TIMESTAMP($2))", 
-                       &[&station.station, &time],
-                       );  
-          match result {
-             Ok(result) => {
-                log::debug!("Successful insert -> created {} row", &result);
-                created_stations.push(station.clone());
-             }   
-             Err(result) => {
-                log::warn!("Insert failed -> {}", &result);
-             }   
-         }   
-      }   
-      log::info!("Created {} out of {} stations in database", 
-                 created_stations.len(), stations_to_create.len());
-   }   
-   return Ok(created_stations);
+/// How the server certificate is checked once TLS is negotiated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verification {
+   /// Encrypt only and accept any certificate
+   EncryptOnly,
+   /// Check the certificate chain against the trusted roots
+   Chain,
+   /// Check the chain and that the certificate names the host
+   Full,
 }
 
-#[allow(dead_code)]
-pub fn update_stations(connection_uri : &str,
-                       schema : &str,
-                       stations_to_update : &Vec<StationTime>) ->
-   Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-
-   let mut updated_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_update.is_empty() {
-       let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-       if !schema.is_empty() {
-          let _ = client.execute("SET search_path TO production", &[])?;
-       }
-       for station in stations_to_update.iter() {
-          let time : f64 = station.time as f64;
-          let result = client.execute(
-                       "UPDATE xml_update SET last_modified = TO_TIMESTAMP($1) WHERE xml_file = $2",
-                       &[&time, &station.station],
+/// Maps the sslmode onto the server certificate checks.  As in libpq, prefer
+/// and require encrypt without checking the certificate, so self-signed
+/// servers still connect; disable returns None since TLS is never negotiated.
+pub fn verification(ssl_mode : SslMode) -> Option<Verification> {
+   match ssl_mode {
+      SslMode::Disable => None,
+      SslMode::Prefer | SslMode::Require => Some(Verification::EncryptOnly),
+      SslMode::VerifyCa => Some(Verification::Chain),
+      SslMode::VerifyFull => Some(Verification::Full),
+   }
+}
+
+fn read_file(file_name : &str, what : &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+   std::fs::read(file_name)
+      .map_err(|error| format!("Failed to read {} {}: {}", what, file_name, error).into())
+}
+
+/// Accepts any server certificate but still checks the handshake signatures.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+   fn verify_server_cert(&self, _end_entity : &CertificateDer<'_>, _intermediates : &[CertificateDer<'_>],
+                         _server_name : &ServerName<'_>, _ocsp_response : &[u8],
+                         _now : UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+      Ok(ServerCertVerified::assertion())
+   }
+
+   fn verify_tls12_signature(&self, message : &[u8], certificate : &CertificateDer<'_>,
+                             signature : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+      rustls::crypto::verify_tls12_signature(message, certificate, signature,
+                                             &self.0.signature_verification_algorithms)
+   }
+
+   fn verify_tls13_signature(&self, message : &[u8], certificate : &CertificateDer<'_>,
+                             signature : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+      rustls::crypto::verify_tls13_signature(message, certificate, signature,
+                                             &self.0.signature_verification_algorithms)
+   }
+
+   fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+      self.0.signature_verification_algorithms.supported_schemes()
+   }
+}
+
+/// Checks the certificate chain but not the host name, as verify-ca does.
+#[derive(Debug)]
+struct IgnoreHostName(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostName {
+   fn verify_server_cert(&self, end_entity : &CertificateDer<'_>, intermediates : &[CertificateDer<'_>],
+                         server_name : &ServerName<'_>, ocsp_response : &[u8],
+                         now : UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+      // The name is checked after the chain so a name mismatch means the chain is good
+      match self.0.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+         Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+       | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })) => {
+            Ok(ServerCertVerified::assertion())
+         }
+         result => result,
+      }
+   }
+
+   fn verify_tls12_signature(&self, message : &[u8], certificate : &CertificateDer<'_>,
+                             signature : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+      self.0.verify_tls12_signature(message, certificate, signature)
+   }
+
+   fn verify_tls13_signature(&self, message : &[u8], certificate : &CertificateDer<'_>,
+                             signature : &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+      self.0.verify_tls13_signature(message, certificate, signature)
+   }
+
+   fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+      self.0.supported_verify_schemes()
+   }
+}
+
+fn create_tls_connector(parameters : &PostgresParameters,
+                        verification : Verification) -> Result<tokio_postgres_rustls::MakeRustlsConnect, Box<dyn std::error::Error>> {
+   let provider = Arc::new(rustls::crypto::ring::default_provider());
+   // Trust the CA bundle if given and the Mozilla roots otherwise
+   let mut roots = rustls::RootCertStore::empty();
+   match &parameters.ssl_root_certificate {
+      Some(root_certificate) => {
+         let pem = read_file(root_certificate, "CA bundle")?;
+         for certificate in CertificateDer::pem_slice_iter(&pem) {
+            roots.add(certificate?)?;
+         }
+      }
+      None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+   }
+   let verifier : Arc<dyn ServerCertVerifier> = match verification {
+      Verification::EncryptOnly => Arc::new(AcceptAnyCertificate(provider.clone())),
+      Verification::Chain | Verification::Full => {
+         let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?;
+         if verification == Verification::Full {
+            webpki
+         }
+         else {
+            Arc::new(IgnoreHostName(webpki))
+         }
+      }
+   };
+   let builder = rustls::ClientConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()?
+      .dangerous()
+      .with_custom_certificate_verifier(verifier);
+   let config = match (&parameters.ssl_certificate, &parameters.ssl_key) {
+      (Some(certificate), Some(key)) => {
+         let certificate_pem = read_file(certificate, "client certificate")?;
+         let key_pem = read_file(key, "client key")?;
+         let certificates = CertificateDer::pem_slice_iter(&certificate_pem).collect::<Result<Vec<_>, _>>()?;
+         builder.with_client_auth_cert(certificates, PrivateKeyDer::from_pem_slice(&key_pem)?)?
+      }
+      _ => builder.with_no_client_auth(),
+   };
+   Ok(tokio_postgres_rustls::MakeRustlsConnect::new(config))
+}
+
+impl Connection {
+   pub fn new(parameters : &PostgresParameters) -> Result<Connection, Box<dyn std::error::Error>> {
+      let mut config = postgres::Config::new();
+      config.host(&parameters.host)
+            .port(parameters.port)
+            .dbname(&parameters.name)
+            .user(&parameters.user)
+            .password(&parameters.password)
+            .application_name("sis_poller");
+      config.ssl_mode(match parameters.ssl_mode {
+         SslMode::Disable => postgres::config::SslMode::Disable,
+         SslMode::Prefer => postgres::config::SslMode::Prefer,
+         SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => postgres::config::SslMode::Require,
+      });
+      let tls = match verification(parameters.ssl_mode) {
+         Some(verification) => Some(create_tls_connector(parameters, verification)?),
+         None => None,
+      };
+      Ok(Connection {config, schema: parameters.schema.clone(), tls})
+   }
+
+   pub fn client(&self) -> Result<postgres::Client, Box<dyn std::error::Error>> {
+      let mut client = match &self.tls {
+         Some(connector) => self.config.connect(connector.clone())?,
+         None => self.config.connect(postgres::NoTls)?,
+      };
+      if !self.schema.is_empty() {
+         let search_path = format!("SET search_path TO \"{}\"", self.schema.replace('"', "\"\""));
+         let _ = client.execute(search_path.as_str(), &[])?;
+      }
+      Ok(client)
+   }
+
+   /// Connects and reads the stations table.
+   pub fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+      let mut client = self.client()?;
+      client.query_one("SELECT COUNT(*) FROM xml_update", &[])?;
+      Ok(())
+   }
+}
+
+/// Applies every migration newer than the recorded schema version in one
+/// transaction.  The schema_version lock keeps concurrent pollers from
+/// applying the same migration twice.
+fn migrate(client : &mut postgres::Client) -> Result<Vec<&'static Migration>, Box<dyn std::error::Error>> {
+   // Skip the baseline once versioned so the server does not log a notice on every connection
+   let versioned : bool = client.query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])?.get(0);
+   if !versioned {
+      client.batch_execute(migrations::POSTGRES_BASELINE)?;
+   }
+   let mut transaction = client.transaction()?;
+   transaction.batch_execute("LOCK TABLE schema_version IN EXCLUSIVE MODE")?;
+   let current : i64 = transaction.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?.get(0);
+   let mut applied : Vec<&'static Migration> = Vec::new();
+   for migration in migrations::POSTGRES.iter().filter(|migration| migration.version > current) {
+      transaction.batch_execute(migration.sql)?;
+      transaction.execute("INSERT INTO schema_version (version, description) VALUES($1, $2)",
+                          &[&migration.version, &migration.description])?;
+      applied.push(migration);
+   }
+   transaction.commit()?;
+   for migration in applied.iter() {
+      log::info!("Applied postgres migration {} -> {}", migration.version, migration.description);
+   }
+   Ok(applied)
+}
+
+/// Owns one postgres client for the lifetime of a run.  The client is
+/// connected on first use, migrated if auto_migrate is set, and
+/// transparently reconnected if the server drops it.
+pub struct Store {
+   connection : Connection,
+   auto_migrate : bool,
+   migrated : bool,
+   client : Option<postgres::Client>,
+}
+
+impl Store {
+   pub fn new(connection : Connection, auto_migrate : bool) -> Store {
+      Store {connection, auto_migrate, migrated: false, client: None}
+   }
+
+   /// Takes a session advisory lock on a dedicated connection, waiting up to
+   /// timeout for another instance to finish.  Returns None if it did not.
+   /// The lock is keyed on the schema so test and production runs do not
+   /// block each other, and is released when the returned client is dropped.
+   pub fn lock(&self, timeout : std::time::Duration) -> Result<Option<postgres::Client>, Box<dyn std::error::Error>> {
+      let mut client = self.connection.client()?;
+      let deadline = std::time::Instant::now() + timeout;
+      loop {
+         let locked : bool
+            = client.query_one("SELECT pg_try_advisory_lock(hashtext('sis_poller.' || current_schema()))", &[])?.get(0);
+         if locked {
+            log::debug!("Took postgres advisory lock");
+            return Ok(Some(client));
+         }
+         let now = std::time::Instant::now();
+         if now >= deadline {
+            return Ok(None);
+         }
+         log::info!("Waiting for another instance to release the postgres advisory lock");
+         std::thread::sleep((deadline - now).min(std::time::Duration::from_secs(1)));
+      }
+   }
+
+   pub fn connection(&self) -> &Connection {
+      &self.connection
+   }
+
+   pub fn migrate(&mut self) -> Result<Vec<&'static Migration>, Box<dyn std::error::Error>> {
+      let applied = migrate(self.client()?)?;
+      self.migrated = true;
+      Ok(applied)
+   }
+
+   pub fn schema_version(&mut self) -> Result<i64, Box<dyn std::error::Error>> {
+      let rows = self.query("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?;
+      Ok(rows[0].get(0))
+   }
+
+   fn client(&mut self) -> Result<&mut postgres::Client, Box<dyn std::error::Error>> {
+      if self.client.as_ref().is_some_and(|client| client.is_closed()) {
+         log::warn!("Lost connection to postgres; reconnecting");
+         self.client = None;
+      }
+      if self.client.is_none() {
+         log::debug!("Connecting to postgres");
+         let mut client = self.connection.client()?;
+         if self.auto_migrate && !self.migrated {
+            migrate(&mut client)?;
+            self.migrated = true;
+         }
+         self.client = Some(client);
+      }
+      Ok(self.client.as_mut().unwrap())
+   }
+
+   /// Runs a statement, reconnecting and retrying once if the connection was lost.
+   fn execute(&mut self,
+              statement : &str,
+              parameters : &[&(dyn postgres::types::ToSql + Sync)]) -> Result<u64, Box<dyn std::error::Error>> {
+      let client = self.client()?;
+      match client.execute(statement, parameters) {
+         Ok(count) => Ok(count),
+         Err(error) if client.is_closed() => {
+            log::warn!("Lost connection to postgres ({}); reconnecting", error);
+            self.client = None;
+            Ok(self.client()?.execute(statement, parameters)?)
+         }
+         Err(error) => Err(error.into()),
+      }
+   }
+
+   /// Runs a query, reconnecting and retrying once if the connection was lost.
+   fn query(&mut self,
+            statement : &str,
+            parameters : &[&(dyn postgres::types::ToSql + Sync)]) -> Result<Vec<postgres::Row>, Box<dyn std::error::Error>> {
+      let client = self.client()?;
+      match client.query(statement, parameters) {
+         Ok(rows) => Ok(rows),
+         Err(error) if client.is_closed() => {
+            log::warn!("Lost connection to postgres ({}); reconnecting", error);
+            self.client = None;
+            Ok(self.client()?.query(statement, parameters)?)
+         }
+         Err(error) => Err(error.into()),
+      }
+   }
+
+   pub fn get_stations(&mut self) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
+      let mut stations : Vec<StationTime> = Vec::new();
+      for row in self.query("SELECT xml_file, EXTRACT(epoch FROM last_modified)::bigint AS last_modified FROM xml_update", &[])? {
+         let station : &str = row.get(0);
+         let time : i64 = row.get(1);
+         let pair = StationTime::from_timestamp(station, time)?;
+         log::debug!("Database station {} {}", station, time);
+         stations.push(pair);
+      }
+      Ok(stations)
+   }
+
+   /// Inserts new stations and updates existing ones whose time differs.
+   pub fn upsert_stations(&mut self,
+                          stations : &[StationTime]) -> Result<UpsertResult, Box<dyn std::error::Error>> {
+      let mut result = UpsertResult::default();
+      if !stations.is_empty() {
+         for station in stations.iter() {
+            let time : f64 = station.time.timestamp() as f64;
+            // No row is returned when the time is unchanged; xmax is 0 only for a fresh insert
+            let rows = self.query(
+                       "INSERT INTO xml_update (xml_file, last_modified) VALUES($1, TO_TIMESTAMP($2)) \
+                        ON CONFLICT (xml_file) DO UPDATE SET last_modified = EXCLUDED.last_modified \
+                        WHERE xml_update.last_modified IS DISTINCT FROM EXCLUDED.last_modified \
+                        RETURNING (xmax = 0) AS inserted",
+                       &[&station.file_name, &time],
                        );
-          match result {
-             Ok(result) => {
-                log::debug!("Successful update -> updated {} row", &result);
-                updated_stations.push(station.clone());
-             }   
-             Err(result) => {
-                log::warn!("update failed -> {}", &result);
-             }   
-         }
-      }   
-      log::info!("Updated {} out of {} stations in database",
-                 updated_stations.len(), stations_to_update.len());
-   }   
-   return Ok(updated_stations);
+            match rows {
+               Ok(rows) if rows.is_empty() => {
+                  log::debug!(station = station.file_name.as_str(), action = "unchanged"; "Station {} is unchanged", station.file_name);
+                  result.unchanged.push(station.clone());
+               }
+               Ok(rows) if rows[0].get::<_, bool>(0) => {
+                  log::debug!(station = station.file_name.as_str(), action = "created"; "Successful upsert -> inserted {}", station.file_name);
+                  result.inserted.push(station.clone());
+               }
+               Ok(_) => {
+                  log::debug!(station = station.file_name.as_str(), action = "updated"; "Successful upsert -> updated {}", station.file_name);
+                  result.updated.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "created"; "Upsert of {} failed -> {}", station.file_name, &error);
+                  result.failed.push(station.clone());
+               }
+            }
+         }
+         log::info!("Inserted {}, updated {}, and left {} unchanged out of {} stations in database",
+                    result.inserted.len(), result.updated.len(), result.unchanged.len(), stations.len());
+      }
+      Ok(result)
+   }
+
+   pub fn update_stations(&mut self,
+                          stations_to_update : &[StationTime]) -> Result<UpdateResult, Box<dyn std::error::Error>> {
+      let mut result = UpdateResult::default();
+      if !stations_to_update.is_empty() {
+         for station in stations_to_update.iter() {
+            let time : f64 = station.time.timestamp() as f64;
+            let count = self.execute(
+                        "UPDATE xml_update SET last_modified = TO_TIMESTAMP($1) WHERE xml_file = $2",
+                        &[&time, &station.file_name],
+                        );
+            match count {
+               Ok(0) => {
+                  log::warn!(station = station.file_name.as_str(), action = "missing"; "Update failed -> station {} is not in the database", station.file_name);
+                  result.missing.push(station.clone());
+               }
+               Ok(count) => {
+                  log::debug!(station = station.file_name.as_str(), action = "updated"; "Successful update -> updated {} row for station {}", count, station.file_name);
+                  result.updated.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "updated"; "Update of {} failed -> {}", station.file_name, &error);
+                  result.failed.push(station.clone());
+               }
+            }
+         }
+         log::info!("Updated {} out of {} stations in database; {} were missing",
+                    result.updated.len(), stations_to_update.len(), result.missing.len());
+      }
+      Ok(result)
+   }
+
+   pub fn remove_stations(&mut self,
+                          stations_to_remove : &[StationTime]) -> Result<RemoveResult, Box<dyn std::error::Error>> {
+      let mut result = RemoveResult::default();
+      if !stations_to_remove.is_empty() {
+         for station in stations_to_remove.iter() {
+            let count = self.execute(
+                         "DELETE FROM xml_update WHERE xml_file = $1",
+                         &[&station.file_name],
+                         );
+            match count {
+               Ok(0) => {
+                  log::warn!(station = station.file_name.as_str(), action = "missing"; "Delete failed -> station {} is not in the database", station.file_name);
+                  result.missing.push(station.clone());
+               }
+               Ok(count) => {
+                  log::debug!(station = station.file_name.as_str(), action = "removed"; "Successful delete -> removed {} row", count);
+                  result.removed.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "removed"; "Delete failed -> {}", &error);
+                  result.failed.push(station.clone());
+               }
+            }
+         }
+         log::info!("Removed {} out of {} stations in database; {} were missing",
+                    result.removed.len(), stations_to_remove.len(), result.missing.len());
+      }
+      Ok(result)
+   }
+
+   pub fn add_history(&mut self,
+                      changes : &[StationChange]) -> Result<(), Box<dyn std::error::Error>> {
+      for change in changes.iter() {
+         let time : f64 = change.time as f64;
+         let detected : f64 = change.detected as f64;
+         self.execute(
+              "INSERT INTO xml_update_history (xml_file, action, last_modified, detected) VALUES($1, $2, TO_TIMESTAMP($3), TO_TIMESTAMP($4))",
+              &[&change.station, &change.action, &time, &detected],
+              )?;
+      }
+      log::debug!("Added {} history entries to database", changes.len());
+      Ok(())
+   }
+
+   pub fn get_history(&mut self) -> Result<Vec<StationChange>, Box<dyn std::error::Error>> {
+      let mut changes : Vec<StationChange> = Vec::new();
+      for row in self.query("SELECT xml_file, action, EXTRACT(epoch FROM last_modified)::bigint, EXTRACT(epoch FROM detected)::bigint FROM xml_update_history ORDER BY detected", &[])? {
+         let station : &str = row.get(0);
+         let action : &str = row.get(1);
+         let change = StationChange {station: station.to_string(),
+                                     action: action.to_string(),
+                                     time: row.get(2),
+                                     detected: row.get(3)};
+         changes.push(change);
+      }
+      Ok(changes)
+   }
+
+   pub fn clear_history(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      Ok(self.execute("DELETE FROM xml_update_history", &[])? as usize)
+   }
+
+   /// Replaces the stored stations, and the history if given, in one
+   /// transaction: upserts the stations, removes the stored stations not
+   /// among them, drops held changes that no longer match a stored station,
+   /// and swaps the history.  Nothing is written if any statement fails.
+   pub fn replace_stations(&mut self,
+                           stations : &[StationTime],
+                           history : Option<&[StationChange]>) -> Result<ReplaceResult, Box<dyn std::error::Error>> {
+      // Checked first since a failed statement aborts the transaction
+      let has_pending = self.schema_version().unwrap_or(0) >= migrations::PENDING_VERSION;
+      let mut transaction = self.client()?.transaction()?;
+      let mut result = ReplaceResult::default();
+      for station in stations.iter() {
+         let time : f64 = station.time.timestamp() as f64;
+         let rows = transaction.query(
+                    "INSERT INTO xml_update (xml_file, last_modified) VALUES($1, TO_TIMESTAMP($2)) \
+                     ON CONFLICT (xml_file) DO UPDATE SET last_modified = EXCLUDED.last_modified \
+                     WHERE xml_update.last_modified IS DISTINCT FROM EXCLUDED.last_modified \
+                     RETURNING (xmax = 0) AS inserted",
+                    &[&station.file_name, &time],
+                    ).map_err(|error| format!("Upsert of {} failed: {}", station.file_name, error))?;
+         match rows.first().map(|row| row.get::<_, bool>(0)) {
+            None => result.upserted.unchanged.push(station.clone()),
+            Some(true) => result.upserted.inserted.push(station.clone()),
+            Some(false) => result.upserted.updated.push(station.clone()),
+         }
+      }
+      let file_names : Vec<&str> = stations.iter().map(|station| station.file_name.as_str()).collect();
+      for row in transaction.query("DELETE FROM xml_update WHERE NOT (xml_file = ANY($1)) \
+                                    RETURNING xml_file, EXTRACT(epoch FROM last_modified)::bigint",
+                                   &[&file_names])? {
+         result.removed.push(StationTime::from_timestamp(row.get(0), row.get(1))?);
+      }
+      if has_pending {
+         result.pending_removed = transaction.execute(
+             "DELETE FROM pending_notification p WHERE NOT EXISTS \
+              (SELECT 1 FROM xml_update x WHERE x.xml_file = p.xml_file AND x.last_modified = p.last_modified)",
+             &[])? as usize;
+      }
+      if let Some(history) = history {
+         result.history_removed = transaction.execute("DELETE FROM xml_update_history", &[])? as usize;
+         for change in history.iter() {
+            let time : f64 = change.time as f64;
+            let detected : f64 = change.detected as f64;
+            transaction.execute(
+                "INSERT INTO xml_update_history (xml_file, action, last_modified, detected) VALUES($1, $2, TO_TIMESTAMP($3), TO_TIMESTAMP($4))",
+                &[&change.station, &change.action, &time, &detected],
+                )?;
+         }
+      }
+      transaction.commit()?;
+      log::info!("Replaced the stations in database: inserted {}, updated {}, left {} unchanged, and removed {}",
+                 result.upserted.inserted.len(), result.upserted.updated.len(),
+                 result.upserted.unchanged.len(), result.removed.len());
+      Ok(result)
+   }
+
+   /// Holds the added and updated stations.  A station already held keeps its
+   /// action, old time, and detection time and takes the new time.
+   pub fn add_pending(&mut self,
+                      changes : &ChangeSet,
+                      detected : i64) -> Result<(), Box<dyn std::error::Error>> {
+      let held : Vec<(&str, Option<f64>, &StationTime)>
+         = changes.added().map(|station| ("created", None, station))
+                  .chain(changes.updated().map(|update| ("updated", update.old_time.map(|e| e.timestamp() as f64), &update.station)))
+                  .collect();
+      let detected : f64 = detected as f64;
+      for (action, old_time, station) in held.iter() {
+         let time : f64 = station.time.timestamp() as f64;
+         self.execute(
+              "INSERT INTO pending_notification (xml_file, action, old_modified, last_modified, detected) \
+               VALUES($1, $2, TO_TIMESTAMP($3), TO_TIMESTAMP($4), TO_TIMESTAMP($5)) \
+               ON CONFLICT (xml_file) DO UPDATE SET last_modified = EXCLUDED.last_modified",
+              &[&station.file_name, action, old_time, &time, &detected],
+              )?;
+      }
+      log::debug!("Held {} pending notifications in database", held.len());
+      Ok(())
+   }
+
+   pub fn get_pending(&mut self) -> Result<Pending, Box<dyn std::error::Error>> {
+      let mut pending = Pending::default();
+      for row in self.query("SELECT xml_file, action, EXTRACT(epoch FROM old_modified)::bigint, EXTRACT(epoch FROM last_modified)::bigint, \
+                             EXTRACT(epoch FROM detected)::bigint FROM pending_notification ORDER BY detected, xml_file", &[])? {
+         let file_name : &str = row.get(0);
+         let action : &str = row.get(1);
+         let old_time : Option<i64> = row.get(2);
+         let detected : i64 = row.get(4);
+         let station = StationTime::from_timestamp(file_name, row.get(3))?;
+         if action == "created" {
+            pending.changes.add(station);
+         }
+         else {
+            let old_time = old_time.map(|e| StationTime::from_timestamp(file_name, e)).transpose()?;
+            pending.changes.update(old_time.map(|e| e.time), station);
+         }
+         pending.since = Some(pending.since.map_or(detected, |since| since.min(detected)));
+      }
+      Ok(pending)
+   }
+
+   pub fn remove_pending(&mut self,
+                         stations : &[StationTime]) -> Result<(), Box<dyn std::error::Error>> {
+      for station in stations.iter() {
+         self.execute("DELETE FROM pending_notification WHERE xml_file = $1", &[&station.file_name])?;
+      }
+      log::debug!("Released {} pending notifications from database", stations.len());
+      Ok(())
+   }
+
+   pub fn add_maintenance(&mut self,
+                          window : &MaintenanceWindow) -> Result<(), Box<dyn std::error::Error>> {
+      let start : f64 = window.start.timestamp() as f64;
+      let end : f64 = window.end.timestamp() as f64;
+      self.execute(
+           "INSERT INTO maintenance_window (starts, ends, reason) VALUES(TO_TIMESTAMP($1), TO_TIMESTAMP($2), $3)",
+           &[&start, &end, &window.reason],
+           )?;
+      Ok(())
+   }
+
+   pub fn get_maintenance(&mut self) -> Result<Vec<MaintenanceWindow>, Box<dyn std::error::Error>> {
+      let mut windows : Vec<MaintenanceWindow> = Vec::new();
+      for row in self.query("SELECT EXTRACT(epoch FROM starts)::bigint, EXTRACT(epoch FROM ends)::bigint, reason \
+                             FROM maintenance_window ORDER BY starts", &[])? {
+         let time = |time : i64| chrono::DateTime::from_timestamp(time, 0)
+                                    .ok_or_else(|| format!("Invalid maintenance window time {}", time));
+         windows.push(MaintenanceWindow::new(time(row.get(0))?, time(row.get(1))?, row.get(2))?);
+      }
+      Ok(windows)
+   }
+
+   pub fn clear_maintenance(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      Ok(self.execute("DELETE FROM maintenance_window", &[])? as usize)
+   }
 }
 
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_verification() {
+      assert_eq!(verification(SslMode::Disable), None);
+      assert_eq!(verification(SslMode::Require), Some(Verification::EncryptOnly));
+      assert_eq!(verification(SslMode::VerifyCa), Some(Verification::Chain));
+      assert_eq!(verification(SslMode::VerifyFull), Some(Verification::Full));
+   }
+
+   #[test]
+   fn test_verification_prefer() {
+      // The default must still connect to servers with self-signed certificates
+      assert_eq!(verification(SslMode::Prefer), Some(Verification::EncryptOnly));
+   }
+
+   #[test]
+   fn test_tls_connector() {
+      let mut parameters = PostgresParameters {host: "localhost".to_string(), port: 5432,
+                                               name: "sis".to_string(), schema: String::new(),
+                                               user: "u".to_string(), password: "p".to_string(),
+                                               ssl_mode: SslMode::Disable, ssl_root_certificate: None,
+                                               ssl_certificate: None, ssl_key: None};
+      assert!(Connection::new(&parameters).unwrap().tls.is_none());
+      for ssl_mode in [SslMode::Prefer, SslMode::Require, SslMode::VerifyCa, SslMode::VerifyFull] {
+         parameters.ssl_mode = ssl_mode;
+         assert!(Connection::new(&parameters).unwrap().tls.is_some());
+      }
+      parameters.ssl_root_certificate = Some("/nonexistent/ca.pem".to_string());
+      assert!(Connection::new(&parameters).is_err());
+   }
+}
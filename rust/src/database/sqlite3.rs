@@ -1,109 +1,599 @@
 use crate::datatypes::station_time::StationTime;
+use crate::datatypes::station_change::StationChange;
+use crate::database::migrations::{self, Migration};
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::maintenance_window::MaintenanceWindow;
+use crate::database::{Pending, RemoveResult, ReplaceResult, UpdateResult, UpsertResult};
+use rusqlite::OptionalExtension;
 
-fn create_xml_table(sqlite3_file : &str ) {
-   log::debug!("Checking if {} exists", sqlite3_file);
-   let sqlite_database_exists = std::fs::exists(sqlite3_file).unwrap();
-   if !sqlite_database_exists {
-      log::info!("Creating sqlite3 database {}", sqlite3_file);
-      let connection
-         = rusqlite::Connection::open(sqlite3_file).unwrap();
-      connection.execute("CREATE TABLE xml_update (xml_file TEXT, last_modified TEXT)", (), ).unwrap();
-      let _ = connection.close();
-   }
-   else {
-      log::debug!("sqlite3 database {} already exists", sqlite3_file);
+/// Owns one sqlite3 connection for the lifetime of a run.  The file is
+/// opened, and pending migrations applied if auto_migrate is set, on first
+/// use.
+pub struct Store {
+   file : String,
+   auto_migrate : bool,
+   connection : Option<rusqlite::Connection>,
+}
+
+/// Applies every migration newer than the recorded schema version, each in
+/// its own transaction.
+fn migrate(connection : &mut rusqlite::Connection) -> Result<Vec<&'static Migration>, Box<dyn std::error::Error>> {
+   connection.execute_batch(migrations::SQLITE3_BASELINE)?;
+   let current = schema_version(connection)?;
+   let mut applied : Vec<&'static Migration> = Vec::new();
+   for migration in migrations::SQLITE3.iter().filter(|migration| migration.version > current) {
+      let transaction = connection.transaction()?;
+      transaction.execute_batch(migration.sql)?;
+      transaction.execute(
+          "INSERT INTO schema_version (version, description, applied) VALUES(?1, ?2, DATETIME('now'))",
+          (&migration.version, &migration.description), )?;
+      transaction.commit()?;
+      log::info!("Applied sqlite3 migration {} -> {}", migration.version, migration.description);
+      applied.push(migration);
    }
+   Ok(applied)
+}
+
+fn schema_version(connection : &rusqlite::Connection) -> Result<i64, Box<dyn std::error::Error>> {
+   let version : i64
+       = connection.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+   Ok(version)
+}
+
+/// Inserts or updates a station.  Returns the stored row's time before the
+/// upsert, None if there was no row, and the number of rows written, which is
+/// 0 if it was unchanged.
+fn upsert(connection : &rusqlite::Connection,
+          station : &StationTime) -> rusqlite::Result<(Option<Option<i64>>, usize)> {
+   // sqlite cannot say which branch of the upsert ran so look first
+   let previous = connection.query_row(
+       "SELECT unixepoch(last_modified) FROM xml_update WHERE xml_file = ?1",
+       (&station.file_name, ), |row| row.get::<_, Option<i64>>(0)).optional()?;
+   let count = connection.execute(
+       "INSERT INTO xml_update (xml_file, last_modified) VALUES(?1, DATETIME(?2, 'unixepoch')) \
+        ON CONFLICT (xml_file) DO UPDATE SET last_modified = excluded.last_modified \
+        WHERE last_modified IS NOT excluded.last_modified",
+       (&station.file_name, &station.time.timestamp()), )?;
+   Ok((previous, count))
+}
+
+fn insert_history(connection : &rusqlite::Connection,
+                  change : &StationChange) -> rusqlite::Result<usize> {
+   connection.execute(
+       "INSERT INTO xml_update_history (xml_file, action, last_modified, detected) VALUES(?1, ?2, DATETIME(?3, 'unixepoch'), DATETIME(?4, 'unixepoch'))",
+       (&change.station, &change.action, &change.time, &change.detected), )
 }
 
-#[allow(dead_code)]
-pub fn create_stations(sqlite3_file : &str,
-                       stations_to_create : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   create_xml_table(sqlite3_file);
-   let mut created_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_create.is_empty() {
-      let connection 
-         = rusqlite::Connection::open(sqlite3_file).unwrap();
-      for station in stations_to_create.iter() {
-         let time : i64 = station.time as i64;
-         let result = connection.execute(
-             "INSERT INTO xml_update (xml_file, last_modified) VALUES(?1, DATETIME(?2, 'unixepoch'))",
-             (&station.station, &time), );
-         match result {
-            Ok(result) => {
-               log::debug!("Successful insert -> created {} row", &result);
-               created_stations.push(station.clone());
+/// Opens the database read-only, so a missing file is an error rather than
+/// created, and reads the stations table.
+pub fn check(file : &str) -> Result<(), Box<dyn std::error::Error>> {
+   let connection = rusqlite::Connection::open_with_flags(file, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+   connection.query_row("SELECT COUNT(*) FROM xml_update", [], |row| row.get::<_, i64>(0))?;
+   Ok(())
+}
+
+impl Store {
+   pub fn new(file : &str, auto_migrate : bool) -> Store {
+      Store {file: file.to_string(), auto_migrate, connection: None}
+   }
+
+   pub fn file(&self) -> &str {
+      &self.file
+   }
+
+   fn connection(&mut self) -> Result<&mut rusqlite::Connection, Box<dyn std::error::Error>> {
+      if self.connection.is_none() {
+         if !std::fs::exists(&self.file)? {
+            log::info!("Creating sqlite3 database {}", self.file);
+         }
+         else {
+            log::debug!("Opening sqlite3 database {}", self.file);
+         }
+         let mut connection = rusqlite::Connection::open(&self.file)?;
+         if self.auto_migrate {
+            migrate(&mut connection)?;
+         }
+         self.connection = Some(connection);
+      }
+      Ok(self.connection.as_mut().unwrap())
+   }
+
+   /// Takes an exclusive lock on a file next to the database, waiting up to
+   /// timeout for another instance to finish.  Returns None if it did not.
+   pub fn lock(&self, timeout : std::time::Duration) -> Result<Option<std::fs::File>, Box<dyn std::error::Error>> {
+      let lock_file = format!("{}.lock", self.file);
+      let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&lock_file)?;
+      let deadline = std::time::Instant::now() + timeout;
+      loop {
+         match file.try_lock() {
+            Ok(()) => {
+               log::debug!("Locked {}", lock_file);
+               return Ok(Some(file));
             }
-            Err(result) => {
-               log::warn!("Insert failed -> {}", &result);
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(error)) => {
+               return Err(format!("Failed to lock {}: {}", lock_file, error).into());
             }
          }
+         let now = std::time::Instant::now();
+         if now >= deadline {
+            return Ok(None);
+         }
+         log::info!("Waiting for another instance to release {}", lock_file);
+         std::thread::sleep((deadline - now).min(std::time::Duration::from_secs(1)));
       }
-      let _ = connection.close();
-      log::info!("Created {} out of {} stations in sqlite3 database", 
-                 created_stations.len(), stations_to_create.len());
    }
-   else {
-      log::debug!("No stations to add to sqlite3");
+
+   pub fn migrate(&mut self) -> Result<Vec<&'static Migration>, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      migrate(connection)
    }
-   return Ok(created_stations);
-}
 
-#[allow(dead_code)]
-pub fn update_stations(sqlite3_file : &str,
-                       stations_to_update : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   //assert!(std::fs::exists(sqlite3_file).unwrap()); // Has to exist
-   let mut updated_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_update.is_empty() {
-      let connection
-         = rusqlite::Connection::open(sqlite3_file).unwrap();
-      assert!(connection.is_autocommit());
-      for station in stations_to_update.iter() {
-         let time : i64 = station.time as i64;
-         let result = connection.execute(
-             "UPDATE xml_update SET last_modified = DATETIME(?1, 'unixepoch') WHERE xml_file = ?2",
-             (&time, &station.station), );
-         match result {
-            Ok(result) => {
-               log::info!("Successfully updated -> station {} to time {}", &result, time);
-               updated_stations.push(station.clone());
+   pub fn schema_version(&mut self) -> Result<i64, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      schema_version(connection)
+   }
+
+   /// Inserts new stations and updates existing ones whose time differs.
+   pub fn upsert_stations(&mut self,
+                          stations : &[StationTime]) -> Result<UpsertResult, Box<dyn std::error::Error>> {
+      let mut result = UpsertResult::default();
+      if !stations.is_empty() {
+         let connection = self.connection()?;
+         for station in stations.iter() {
+            match upsert(connection, station) {
+               Ok((_, 0)) => {
+                  log::debug!(station = station.file_name.as_str(), action = "unchanged"; "Station {} is unchanged", station.file_name);
+                  result.unchanged.push(station.clone());
+               }
+               Ok((None, _)) => {
+                  log::debug!(station = station.file_name.as_str(), action = "created"; "Successful upsert -> inserted {}", station.file_name);
+                  result.inserted.push(station.clone());
+               }
+               Ok((Some(_), _)) => {
+                  log::debug!(station = station.file_name.as_str(), action = "updated"; "Successful upsert -> updated {}", station.file_name);
+                  result.updated.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "created"; "Upsert of {} failed -> {}", station.file_name, &error);
+                  result.failed.push(station.clone());
+               }
             }
-            Err(result) => {
-               log::warn!("Insert failed -> {}", &result);
+         }
+         log::info!("Inserted {}, updated {}, and left {} unchanged out of {} stations in sqlite3 database",
+                    result.inserted.len(), result.updated.len(), result.unchanged.len(), stations.len());
+      }
+      else {
+         log::debug!("No stations to add to sqlite3");
+      }
+      Ok(result)
+   }
+
+   pub fn update_stations(&mut self,
+                          stations_to_update : &[StationTime]) -> Result<UpdateResult, Box<dyn std::error::Error>> {
+      let mut result = UpdateResult::default();
+      if !stations_to_update.is_empty() {
+         let connection = self.connection()?;
+         for station in stations_to_update.iter() {
+            let time : i64 = station.time.timestamp();
+            let count = connection.execute(
+                "UPDATE xml_update SET last_modified = DATETIME(?1, 'unixepoch') WHERE xml_file = ?2",
+                (&time, &station.file_name), );
+            match count {
+               Ok(0) => {
+                  log::warn!(station = station.file_name.as_str(), action = "missing"; "Update failed -> station {} is not in the database", station.file_name);
+                  result.missing.push(station.clone());
+               }
+               Ok(count) => {
+                  log::debug!(station = station.file_name.as_str(), action = "updated"; "Successful update -> updated {} row for station {}", count, station.file_name);
+                  result.updated.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "updated"; "Update of {} failed -> {}", station.file_name, &error);
+                  result.failed.push(station.clone());
+               }
             }
          }
+         log::info!("Updated {} out of {} stations in sqlite3 database; {} were missing",
+                    result.updated.len(), stations_to_update.len(), result.missing.len());
+      }
+      else {
+         log::debug!("No stations to update in sqlite3");
       }
-      let _ = connection.close();
-      log::info!("Updated {} out of {} stations in sqlite3 database",
-                 updated_stations.len(), stations_to_update.len());
+      Ok(result)
    }
-   else {
-      log::debug!("No stations to add to sqlite3");
+
+   pub fn get_stations(&mut self) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      let mut stations : Vec<StationTime> = Vec::new();
+      let mut statement
+          = connection.prepare("SELECT xml_file, unixepoch(last_modified) AS last_modified FROM xml_update")?;
+      let station_iter = statement.query_map([], |row| {
+         Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+      })?;
+
+      for s in station_iter {
+         let (xml_file, time) = s?;
+         let station = StationTime::from_timestamp(&xml_file, time)?;
+         log::debug!("Found station {:?} in sqlite3", station);
+         stations.push(station.clone());
+      }
+      Ok(stations)
+   }
+
+   pub fn remove_stations(&mut self,
+                          stations_to_remove : &[StationTime]) -> Result<RemoveResult, Box<dyn std::error::Error>> {
+      let mut result = RemoveResult::default();
+      if !stations_to_remove.is_empty() {
+         let connection = self.connection()?;
+         for station in stations_to_remove.iter() {
+            let count = connection.execute(
+                "DELETE FROM xml_update WHERE xml_file = ?1",
+                (&station.file_name, ), );
+            match count {
+               Ok(0) => {
+                  log::warn!(station = station.file_name.as_str(), action = "missing"; "Delete failed -> station {} is not in the database", station.file_name);
+                  result.missing.push(station.clone());
+               }
+               Ok(count) => {
+                  log::debug!(station = station.file_name.as_str(), action = "removed"; "Successful delete -> removed {} row", count);
+                  result.removed.push(station.clone());
+               }
+               Err(error) => {
+                  log::warn!(station = station.file_name.as_str(), action = "removed"; "Delete failed -> {}", &error);
+                  result.failed.push(station.clone());
+               }
+            }
+         }
+         log::info!("Removed {} out of {} stations in sqlite3 database; {} were missing",
+                    result.removed.len(), stations_to_remove.len(), result.missing.len());
+      }
+      else {
+         log::debug!("No stations to remove from sqlite3");
+      }
+      Ok(result)
+   }
+
+   pub fn add_history(&mut self,
+                      changes : &[StationChange]) -> Result<(), Box<dyn std::error::Error>> {
+      if changes.is_empty() {
+         return Ok(());
+      }
+      let connection = self.connection()?;
+      for change in changes.iter() {
+         insert_history(connection, change)?;
+      }
+      log::debug!("Added {} history entries to sqlite3 database", changes.len());
+      Ok(())
+   }
+
+   pub fn get_history(&mut self) -> Result<Vec<StationChange>, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      let mut statement
+          = connection.prepare("SELECT xml_file, action, unixepoch(last_modified), unixepoch(detected) FROM xml_update_history ORDER BY detected")?;
+      let change_iter = statement.query_map([], |row| {
+         Ok(StationChange {
+             station: row.get(0)?,
+             action: row.get(1)?,
+             time: row.get(2)?,
+             detected: row.get(3)?,
+           })
+      })?;
+      let mut changes : Vec<StationChange> = Vec::new();
+      for change in change_iter {
+         changes.push(change?);
+      }
+      Ok(changes)
+   }
+
+   pub fn clear_history(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      Ok(connection.execute("DELETE FROM xml_update_history", [])?)
+   }
+
+   /// Replaces the stored stations, and the history if given, in one
+   /// transaction: upserts the stations, removes the stored stations not
+   /// among them, drops held changes that no longer match a stored station,
+   /// and swaps the history.  Nothing is written if any statement fails.
+   pub fn replace_stations(&mut self,
+                           stations : &[StationTime],
+                           history : Option<&[StationChange]>) -> Result<ReplaceResult, Box<dyn std::error::Error>> {
+      // An unversioned store has no pending_notification table
+      let has_pending = self.schema_version().unwrap_or(0) >= migrations::PENDING_VERSION;
+      let connection = self.connection()?;
+      let transaction = connection.transaction()?;
+      let mut result = ReplaceResult::default();
+      for station in stations.iter() {
+         match upsert(&transaction, station).map_err(|error| format!("Upsert of {} failed: {}", station.file_name, error))? {
+            (_, 0) => result.upserted.unchanged.push(station.clone()),
+            (None, _) => result.upserted.inserted.push(station.clone()),
+            (Some(_), _) => result.upserted.updated.push(station.clone()),
+         }
+      }
+      let stored : Vec<(String, i64)> = transaction
+          .prepare("SELECT xml_file, unixepoch(last_modified) FROM xml_update")?
+          .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+          .collect::<Result<_, _>>()?;
+      for (file_name, time) in stored.iter().filter(|(file_name, _)| !stations.iter().any(|e| &e.file_name == file_name)) {
+         transaction.execute("DELETE FROM xml_update WHERE xml_file = ?1", (file_name, ))?;
+         result.removed.push(StationTime::from_timestamp(file_name, *time)?);
+      }
+      if has_pending {
+         result.pending_removed = transaction.execute(
+             "DELETE FROM pending_notification WHERE NOT EXISTS \
+              (SELECT 1 FROM xml_update WHERE xml_update.xml_file = pending_notification.xml_file \
+                 AND unixepoch(xml_update.last_modified) = unixepoch(pending_notification.last_modified))", [])?;
+      }
+      if let Some(history) = history {
+         result.history_removed = transaction.execute("DELETE FROM xml_update_history", [])?;
+         for change in history.iter() {
+            insert_history(&transaction, change)?;
+         }
+      }
+      transaction.commit()?;
+      log::info!("Replaced the stations in sqlite3 database: inserted {}, updated {}, left {} unchanged, and removed {}",
+                 result.upserted.inserted.len(), result.upserted.updated.len(),
+                 result.upserted.unchanged.len(), result.removed.len());
+      Ok(result)
+   }
+
+   /// Holds the added and updated stations.  A station already held keeps its
+   /// action, old time, and detection time and takes the new time.
+   pub fn add_pending(&mut self,
+                      changes : &ChangeSet,
+                      detected : i64) -> Result<(), Box<dyn std::error::Error>> {
+      let held : Vec<(&str, Option<i64>, &StationTime)>
+         = changes.added().map(|station| ("created", None, station))
+                  .chain(changes.updated().map(|update| ("updated", update.old_time.map(|e| e.timestamp()), &update.station)))
+                  .collect();
+      if held.is_empty() {
+         return Ok(());
+      }
+      let connection = self.connection()?;
+      for (action, old_time, station) in held.iter() {
+         connection.execute(
+             "INSERT INTO pending_notification (xml_file, action, old_modified, last_modified, detected) \
+              VALUES(?1, ?2, DATETIME(?3, 'unixepoch'), DATETIME(?4, 'unixepoch'), DATETIME(?5, 'unixepoch')) \
+              ON CONFLICT (xml_file) DO UPDATE SET last_modified = excluded.last_modified",
+             (&station.file_name, action, old_time, &station.time.timestamp(), &detected), )?;
+      }
+      log::debug!("Held {} pending notifications in sqlite3 database", held.len());
+      Ok(())
+   }
+
+   pub fn get_pending(&mut self) -> Result<Pending, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      let mut statement
+          = connection.prepare("SELECT xml_file, action, unixepoch(old_modified), unixepoch(last_modified), unixepoch(detected) \
+                                FROM pending_notification ORDER BY detected, xml_file")?;
+      let row_iter = statement.query_map([], |row| {
+         Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i64>>(2)?,
+             row.get::<_, i64>(3)?, row.get::<_, i64>(4)?))
+      })?;
+      let mut pending = Pending::default();
+      for row in row_iter {
+         let (file_name, action, old_time, time, detected) = row?;
+         let station = StationTime::from_timestamp(&file_name, time)?;
+         if action == "created" {
+            pending.changes.add(station);
+         }
+         else {
+            let old_time = old_time.map(|e| StationTime::from_timestamp(&file_name, e)).transpose()?;
+            pending.changes.update(old_time.map(|e| e.time), station);
+         }
+         pending.since = Some(pending.since.map_or(detected, |since| since.min(detected)));
+      }
+      Ok(pending)
+   }
+
+   pub fn remove_pending(&mut self,
+                         stations : &[StationTime]) -> Result<(), Box<dyn std::error::Error>> {
+      if stations.is_empty() {
+         return Ok(());
+      }
+      let connection = self.connection()?;
+      for station in stations.iter() {
+         connection.execute("DELETE FROM pending_notification WHERE xml_file = ?1", (&station.file_name,))?;
+      }
+      log::debug!("Released {} pending notifications from sqlite3 database", stations.len());
+      Ok(())
    }
-   return Ok(updated_stations);
-}
 
-#[allow(dead_code)]
-pub fn get_stations(sqlite3_file : &str) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   // Make sure the sqlite3 file exists
-   create_xml_table(sqlite3_file);
-   //database::sqlite3::create_xml_table(sqlite3_file);
-   assert!(std::fs::exists(sqlite3_file).unwrap());
-   let mut stations : Vec<StationTime> = Vec::new();
-   let connection = rusqlite::Connection::open(sqlite3_file).unwrap(); 
-   let mut statement
-       = connection.prepare("SELECT xml_file, unixepoch(last_modified) AS last_modified FROM xml_update")?;
-   let station_iter = statement.query_map([], |row| {
-      Ok(StationTime {
-          station: row.get(0)?, 
-          time: row.get(1)?,
-        })  
-   })?; 
-
-   for s in station_iter {
-      let station = s?; 
-      log::debug!("Found station {:?} in sqlite3", station);
-      stations.push(station.clone());
-   }   
-   return Ok(stations);
+   pub fn add_maintenance(&mut self,
+                          window : &MaintenanceWindow) -> Result<(), Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      connection.execute(
+          "INSERT INTO maintenance_window (starts, ends, reason) VALUES(DATETIME(?1, 'unixepoch'), DATETIME(?2, 'unixepoch'), ?3)",
+          (&window.start.timestamp(), &window.end.timestamp(), &window.reason), )?;
+      Ok(())
+   }
+
+   pub fn get_maintenance(&mut self) -> Result<Vec<MaintenanceWindow>, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      let mut statement
+          = connection.prepare("SELECT unixepoch(starts), unixepoch(ends), reason FROM maintenance_window ORDER BY starts")?;
+      let row_iter = statement.query_map([], |row| {
+         Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, Option<String>>(2)?))
+      })?;
+      let mut windows : Vec<MaintenanceWindow> = Vec::new();
+      for row in row_iter {
+         let (start, end, reason) = row?;
+         let time = |time : i64| chrono::DateTime::from_timestamp(time, 0)
+                                    .ok_or_else(|| format!("Invalid maintenance window time {}", time));
+         windows.push(MaintenanceWindow::new(time(start)?, time(end)?, reason)?);
+      }
+      Ok(windows)
+   }
+
+   pub fn clear_maintenance(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+      let connection = self.connection()?;
+      Ok(connection.execute("DELETE FROM maintenance_window", [])?)
+   }
 }
 
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_store_round_trip() {
+      let file = std::env::temp_dir().join(format!("sis_poller_store_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      let mut store = Store::new(file.to_str().unwrap(), true);
+      // Nothing is created until the store is used
+      assert!(!file.exists());
+      assert!(check(file.to_str().unwrap()).is_err());
+      assert!(store.get_stations().unwrap().is_empty());
+      assert!(file.exists());
+      assert!(check(file.to_str().unwrap()).is_ok());
+
+      let stations = vec![StationTime::from_timestamp("UU_ALP.xml", 1685438940).unwrap()];
+      assert_eq!(store.upsert_stations(&stations).unwrap().inserted.len(), 1);
+      assert_eq!(store.upsert_stations(&stations).unwrap().unchanged.len(), 1);
+      let updated = vec![StationTime::from_timestamp("UU_ALP.xml", 1685439000).unwrap()];
+      assert_eq!(store.update_stations(&updated).unwrap().updated.len(), 1);
+      let missing = vec![StationTime::from_timestamp("UU_BGU.xml", 1685439000).unwrap()];
+      let result = store.update_stations(&missing).unwrap();
+      assert!(result.updated.is_empty());
+      assert_eq!(result.missing.len(), 1);
+      let stored = store.get_stations().unwrap();
+      assert_eq!(stored.len(), 1);
+      assert_eq!(stored[0].time.timestamp(), 1685439000);
+      assert_eq!(store.remove_stations(&updated).unwrap().removed.len(), 1);
+      assert!(store.get_stations().unwrap().is_empty());
+      // Already removed
+      let result = store.remove_stations(&updated).unwrap();
+      assert!(result.removed.is_empty());
+      assert_eq!(result.missing.len(), 1);
+      std::fs::remove_file(&file).unwrap();
+   }
+
+   #[test]
+   fn test_pending() {
+      let file = std::env::temp_dir().join(format!("sis_poller_pending_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      let mut store = Store::new(file.to_str().unwrap(), true);
+      assert!(store.get_pending().unwrap().since.is_none());
+      let station = |file_name : &str, time : i64| StationTime::from_timestamp(file_name, time).unwrap();
+      let mut changes = ChangeSet::new();
+      changes.add(station("UU_NEW.xml", 20));
+      changes.update(Some(station("UU_ALP.xml", 10).time), station("UU_ALP.xml", 20));
+      store.add_pending(&changes, 100).unwrap();
+      // Held again: the first old and detection times are kept
+      let mut later = ChangeSet::new();
+      later.update(Some(station("UU_NEW.xml", 20).time), station("UU_NEW.xml", 30));
+      later.update(Some(station("UU_ALP.xml", 20).time), station("UU_ALP.xml", 30));
+      store.add_pending(&later, 200).unwrap();
+      let pending = store.get_pending().unwrap();
+      assert_eq!(pending.since, Some(100));
+      assert_eq!(pending.changes.added().map(|e| e.time.timestamp()).collect::<Vec<_>>(), vec![30]);
+      let update = pending.changes.updated().next().unwrap();
+      assert_eq!(update.old_time.unwrap().timestamp(), 10);
+      assert_eq!(update.new_time().timestamp(), 30);
+      store.remove_pending(&[station("UU_NEW.xml", 30)]).unwrap();
+      assert_eq!(store.get_pending().unwrap().changes.added().count(), 0);
+      assert_eq!(store.get_pending().unwrap().changes.updated().count(), 1);
+      std::fs::remove_file(&file).unwrap();
+   }
+
+   #[test]
+   fn test_replace_stations() {
+      let file = std::env::temp_dir().join(format!("sis_poller_replace_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      let mut store = Store::new(file.to_str().unwrap(), true);
+      let station = |file_name : &str, time : i64| StationTime::from_timestamp(file_name, time).unwrap();
+      let change = |file_name : &str| StationChange {station: file_name.to_string(), action: String::from("created"),
+                                                     time: 10, detected: 10};
+      store.upsert_stations(&[station("UU_ALP.xml", 10), station("UU_OLD.xml", 10)]).unwrap();
+      store.add_history(&[change("UU_ALP.xml"), change("UU_OLD.xml")]).unwrap();
+      let mut held = ChangeSet::new();
+      held.update(Some(station("UU_OLD.xml", 5).time), station("UU_OLD.xml", 10));
+      held.update(Some(station("UU_ALP.xml", 5).time), station("UU_ALP.xml", 10));
+      store.add_pending(&held, 10).unwrap();
+
+      // The second upsert fails, so nothing, including the first, is written
+      store.connection().unwrap().execute_batch("
+         CREATE TRIGGER reject_bad BEFORE INSERT ON xml_update WHEN NEW.xml_file = 'UU_BAD.xml'
+         BEGIN SELECT RAISE(ABORT, 'rejected'); END;
+      ").unwrap();
+      let snapshot = [station("UU_NEW.xml", 20), station("UU_BAD.xml", 20), station("UU_ALP.xml", 20)];
+      assert!(store.replace_stations(&snapshot, Some(&[change("UU_NEW.xml")])).is_err());
+      let mut stored : Vec<String> = store.get_stations().unwrap().into_iter().map(|e| e.file_name).collect();
+      stored.sort();
+      assert_eq!(stored, vec!["UU_ALP.xml", "UU_OLD.xml"]);
+      assert_eq!(store.get_history().unwrap().len(), 2);
+      assert_eq!(store.get_pending().unwrap().changes.updated().count(), 2);
+
+      let result = store.replace_stations(&[station("UU_NEW.xml", 20), station("UU_ALP.xml", 20)],
+                                          Some(&[change("UU_NEW.xml")])).unwrap();
+      assert_eq!(result.upserted.inserted.len(), 1);
+      assert_eq!(result.upserted.updated.len(), 1);
+      assert_eq!(result.removed, vec![station("UU_OLD.xml", 10)]);
+      assert_eq!(result.history_removed, 2);
+      // Neither held change matches a stored station any more
+      assert_eq!(result.pending_removed, 2);
+      assert_eq!(store.get_stations().unwrap().len(), 2);
+      assert_eq!(store.get_history().unwrap(), vec![change("UU_NEW.xml")]);
+      assert!(store.get_pending().unwrap().since.is_none());
+
+      // Without history the stored history is kept
+      store.replace_stations(&[station("UU_NEW.xml", 20)], None).unwrap();
+      assert_eq!(store.get_history().unwrap().len(), 1);
+      std::fs::remove_file(&file).unwrap();
+   }
+
+   #[test]
+   fn test_maintenance() {
+      let file = std::env::temp_dir().join(format!("sis_poller_maintenance_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      let mut store = Store::new(file.to_str().unwrap(), true);
+      assert!(store.get_maintenance().unwrap().is_empty());
+      let mut window = MaintenanceWindow::parse("2023-05-30 09:29/2023-05-30 12:00").unwrap();
+      window.reason = Some(String::from("Metadata campaign"));
+      store.add_maintenance(&window).unwrap();
+      assert_eq!(store.get_maintenance().unwrap(), vec![window]);
+      assert_eq!(store.clear_maintenance().unwrap(), 1);
+      assert!(store.get_maintenance().unwrap().is_empty());
+      std::fs::remove_file(&file).unwrap();
+   }
+
+   #[test]
+   fn test_lock() {
+      let file = std::env::temp_dir().join(format!("sis_poller_lock_{}.sqlite3", std::process::id()));
+      let store = Store::new(file.to_str().unwrap(), true);
+      let timeout = std::time::Duration::from_millis(100);
+      let lock = store.lock(timeout).unwrap();
+      assert!(lock.is_some());
+      assert!(store.lock(timeout).unwrap().is_none());
+      drop(lock);
+      assert!(store.lock(timeout).unwrap().is_some());
+      std::fs::remove_file(format!("{}.lock", file.to_str().unwrap())).unwrap();
+   }
+
+   #[test]
+   fn test_migrations() {
+      let file = std::env::temp_dir().join(format!("sis_poller_migrations_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      {
+         // A database from before versioning with a duplicated file
+         let connection = rusqlite::Connection::open(&file).unwrap();
+         connection.execute_batch("
+            CREATE TABLE xml_update (xml_file TEXT, last_modified TEXT);
+            INSERT INTO xml_update VALUES ('UU_ALP.xml', DATETIME(1685439000, 'unixepoch'));
+            INSERT INTO xml_update VALUES ('UU_ALP.xml', DATETIME(1685438940, 'unixepoch'));
+         ").unwrap();
+      }
+      let mut store = Store::new(file.to_str().unwrap(), false);
+      assert_eq!(store.migrate().unwrap().len(), migrations::SQLITE3.len());
+      assert_eq!(store.schema_version().unwrap(), migrations::SQLITE3.len() as i64);
+      assert!(store.migrate().unwrap().is_empty());
+      let stored = store.get_stations().unwrap();
+      assert_eq!(stored.len(), 1);
+      assert_eq!(stored[0].time.timestamp(), 1685439000);
+      // The unique key turns a second insert of the same file into an update
+      let newer = vec![StationTime::from_timestamp("UU_ALP.xml", 1685439060).unwrap()];
+      assert_eq!(store.upsert_stations(&newer).unwrap().updated.len(), 1);
+      assert_eq!(store.get_stations().unwrap().len(), 1);
+      std::fs::remove_file(&file).unwrap();
+   }
+}
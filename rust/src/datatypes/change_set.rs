@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use crate::datatypes::station_time::StationTime;
+
+/// A station SIS modified after the stored time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StationUpdate {
+   /// The stored time; None if another instance stored the station after
+   /// this poll read the database
+   pub old_time : Option<DateTime<Utc>>,
+   /// The station as SIS now lists it
+   pub station : StationTime,
+}
+
+impl StationUpdate {
+   pub fn new_time(&self) -> DateTime<Utc> {
+      self.station.time
+   }
+}
+
+/// The changes to one network.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct NetworkChanges {
+   /// Listed by SIS but not stored
+   pub added : Vec<StationTime>,
+   pub updated : Vec<StationUpdate>,
+   /// Stored but no longer listed by SIS
+   pub removed : Vec<StationTime>,
+   pub unchanged : Vec<StationTime>,
+   /// Set if so much of the network changed at once, e.g., SIS re-exported
+   /// it, that the notification condenses it
+   #[serde(default)]
+   pub bulk : bool,
+}
+
+impl NetworkChanges {
+   /// Records an added or updated station, keeping one change per station.
+   fn merge(&mut self, added : bool, old_time : Option<DateTime<Utc>>, station : StationTime) {
+      if let Some(existing) = self.added.iter_mut().find(|e| e.file_name == station.file_name) {
+         if station.time > existing.time {
+            *existing = station;
+         }
+      }
+      else if let Some(index) = self.updated.iter().position(|e| e.station.file_name == station.file_name) {
+         let existing = &mut self.updated[index];
+         // The earlier change's stored time is the one the station had before both
+         let old_time = if station.time > existing.station.time { existing.old_time } else { old_time };
+         let station = if station.time > existing.station.time { station } else { existing.station.clone() };
+         if added {
+            self.updated.remove(index);
+            self.added.push(station);
+         }
+         else {
+            *existing = StationUpdate {old_time, station};
+         }
+      }
+      else if added {
+         self.added.push(station);
+      }
+      else {
+         self.updated.push(StationUpdate {old_time, station});
+      }
+   }
+}
+
+/// The difference between SIS and the database, grouped by network.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ChangeSet {
+   pub networks : BTreeMap<String, NetworkChanges>,
+}
+
+impl ChangeSet {
+   pub fn new() -> ChangeSet {
+      ChangeSet::default()
+   }
+
+   fn network(&mut self, network : &str) -> &mut NetworkChanges {
+      self.networks.entry(network.to_string()).or_default()
+   }
+
+   pub fn add(&mut self, station : StationTime) {
+      self.network(&station.network).added.push(station);
+   }
+
+   pub fn update(&mut self, old_time : Option<DateTime<Utc>>, station : StationTime) {
+      self.network(&station.network).updated.push(StationUpdate {old_time, station});
+   }
+
+   pub fn remove(&mut self, station : StationTime) {
+      self.network(&station.network).removed.push(station);
+   }
+
+   pub fn keep(&mut self, station : StationTime) {
+      self.network(&station.network).unchanged.push(station);
+   }
+
+   pub fn added(&self) -> impl Iterator<Item = &StationTime> {
+      self.networks.values().flat_map(|changes| changes.added.iter())
+   }
+
+   pub fn updated(&self) -> impl Iterator<Item = &StationUpdate> {
+      self.networks.values().flat_map(|changes| changes.updated.iter())
+   }
+
+   pub fn removed(&self) -> impl Iterator<Item = &StationTime> {
+      self.networks.values().flat_map(|changes| changes.removed.iter())
+   }
+
+   pub fn unchanged(&self) -> impl Iterator<Item = &StationTime> {
+      self.networks.values().flat_map(|changes| changes.unchanged.iter())
+   }
+
+   /// The changes to the stations for which keep returns true.
+   pub fn filter(&self, keep : impl Fn(&StationTime) -> bool) -> ChangeSet {
+      let mut result = ChangeSet::new();
+      for station in self.added().filter(|e| keep(e)) {
+         result.add(station.clone());
+      }
+      for update in self.updated().filter(|e| keep(&e.station)) {
+         result.update(update.old_time, update.station.clone());
+      }
+      for station in self.removed().filter(|e| keep(e)) {
+         result.remove(station.clone());
+      }
+      for station in self.unchanged().filter(|e| keep(e)) {
+         result.keep(station.clone());
+      }
+      for (name, network) in result.networks.iter_mut() {
+         network.bulk = self.networks[name].bulk;
+      }
+      result
+   }
+
+   /// Appends the changes in other.
+   pub fn extend(&mut self, other : ChangeSet) {
+      for (name, changes) in other.networks {
+         let network = self.network(&name);
+         network.added.extend(changes.added);
+         network.updated.extend(changes.updated);
+         network.removed.extend(changes.removed);
+         network.unchanged.extend(changes.unchanged);
+         network.bulk |= changes.bulk;
+      }
+   }
+
+   /// Appends the changes in other, keeping one change per station: the
+   /// newest time, the stored time before the earliest change, and added if
+   /// either change added the station.
+   pub fn merge(&mut self, other : ChangeSet) {
+      for (name, changes) in other.networks {
+         let network = self.network(&name);
+         for station in changes.added {
+            network.merge(true, None, station);
+         }
+         for update in changes.updated {
+            network.merge(false, update.old_time, update.station);
+         }
+         network.removed.extend(changes.removed);
+         network.unchanged.extend(changes.unchanged);
+         network.bulk |= changes.bulk;
+      }
+   }
+
+   /// True if a station was added or updated, i.e., there is something to
+   /// announce.  Removals are not announced.
+   pub fn has_changes(&self) -> bool {
+      self.added().next().is_some() || self.updated().next().is_some()
+   }
+
+   pub fn to_json(&self) -> serde_json::Value {
+      serde_json::to_value(self).unwrap_or_default()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_change_set() {
+      let station = |file_name : &str, time : i64| StationTime::from_timestamp(file_name, time).unwrap();
+      let mut changes = ChangeSet::new();
+      assert!(!changes.has_changes());
+      changes.keep(station("WY_YHB.xml", 10));
+      changes.remove(station("UU_OLD.xml", 10));
+      assert!(!changes.has_changes());
+      changes.add(station("UU_NEW.xml", 20));
+      changes.update(Some(station("UU_ALP.xml", 10).time), station("UU_ALP.xml", 20));
+      assert!(changes.has_changes());
+      assert_eq!(changes.networks.len(), 2);
+      assert_eq!(changes.networks["UU"].added.len(), 1);
+      assert_eq!(changes.updated().next().unwrap().new_time().timestamp(), 20);
+
+      let json = changes.to_json();
+      assert_eq!(json["networks"]["UU"]["updated"][0]["station"]["file_name"], "UU_ALP.xml");
+      assert_eq!(json["networks"]["WY"]["unchanged"][0]["station"], "YHB");
+      assert_eq!(serde_json::from_value::<ChangeSet>(json).unwrap(), changes);
+
+      let wy = changes.filter(|station| station.network == "WY");
+      assert_eq!(wy.networks.len(), 1);
+      assert_eq!(wy.unchanged().count(), 1);
+      assert!(!wy.has_changes());
+      changes.networks.get_mut("UU").unwrap().bulk = true;
+      let mut uu = changes.filter(|station| station.network == "UU");
+      assert!(uu.networks["UU"].bulk);
+      uu.extend(wy);
+      assert_eq!(uu, changes);
+   }
+
+   #[test]
+   fn test_merge() {
+      let station = |file_name : &str, time : i64| StationTime::from_timestamp(file_name, time).unwrap();
+      let mut changes = ChangeSet::new();
+      changes.update(Some(station("UU_ALP.xml", 20).time), station("UU_ALP.xml", 30));
+      changes.update(Some(station("UU_BEE.xml", 10).time), station("UU_BEE.xml", 20));
+      changes.add(station("UU_NEW.xml", 40));
+      let mut held = ChangeSet::new();
+      held.update(Some(station("UU_ALP.xml", 10).time), station("UU_ALP.xml", 20));
+      held.add(station("UU_BEE.xml", 15));
+      held.add(station("UU_NEW.xml", 30));
+      held.update(None, station("WY_YHB.xml", 10));
+      changes.merge(held);
+      assert_eq!(changes.added().count() + changes.updated().count(), 4);
+      let alp = changes.updated().find(|e| e.station.file_name == "UU_ALP.xml").unwrap();
+      assert_eq!(alp.old_time.unwrap().timestamp(), 10);
+      assert_eq!(alp.new_time().timestamp(), 30);
+      // Added while held and since updated is still added
+      let bee = changes.added().find(|e| e.file_name == "UU_BEE.xml").unwrap();
+      assert_eq!(bee.time.timestamp(), 20);
+      assert_eq!(changes.added().find(|e| e.file_name == "UU_NEW.xml").unwrap().time.timestamp(), 40);
+      assert_eq!(changes.networks["WY"].updated.len(), 1);
+   }
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use crate::parser::{format_time, parse_time};
+
+/// A period during which every notification is held, e.g., a planned
+/// metadata campaign.  Held changes are announced once it ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+   pub start : DateTime<Utc>,
+   pub end : DateTime<Utc>,
+   /// Why notifications are muted, e.g., a ticket
+   pub reason : Option<String>,
+}
+
+impl MaintenanceWindow {
+   pub fn new(start : DateTime<Utc>,
+              end : DateTime<Utc>,
+              reason : Option<String>) -> Result<MaintenanceWindow, String> {
+      if end <= start {
+         return Err(format!("The window ends at {}, before it starts at {}",
+                            format_time(end.timestamp()), format_time(start.timestamp())));
+      }
+      Ok(MaintenanceWindow {start, end, reason})
+   }
+
+   /// Parses a window written as start/end, e.g.,
+   /// 2026-10-20 08:00/2026-10-21 18:00.
+   pub fn parse(value : &str) -> Result<MaintenanceWindow, String> {
+      let (start, end) = value.split_once('/')
+         .ok_or_else(|| format!("'{}' is not a window; expected start/end, e.g., 2026-10-20 08:00/2026-10-21 18:00", value))?;
+      MaintenanceWindow::new(parse_time(start)?, parse_time(end)?, None)
+   }
+
+   pub fn contains(&self, time : DateTime<Utc>) -> bool {
+      self.start <= time && time < self.end
+   }
+}
+
+impl std::fmt::Display for MaintenanceWindow {
+   fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+      write!(f, "{}/{}", format_time(self.start.timestamp()), format_time(self.end.timestamp()))
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_parse() {
+      let window = MaintenanceWindow::parse("2023-05-30 09:29/2023-05-30T12:00:00Z").unwrap();
+      assert!(window.contains(window.start));
+      assert!(!window.contains(window.end));
+      assert_eq!(MaintenanceWindow::parse(&window.to_string()).unwrap(), window);
+      assert!(MaintenanceWindow::parse("2023-05-30 09:29").is_err());
+      assert!(MaintenanceWindow::parse("2023-05-30 09:29/2023-05-30 09:00").is_err());
+   }
+}
@@ -1,2 +1,6 @@
 pub mod station_time;
+pub mod station_change;
+pub mod run_summary;
+pub mod change_set;
+pub mod maintenance_window;
 //pub use self::datatypes::StationTime;
@@ -0,0 +1,48 @@
+/// What one poll did.  Logged as the summary event at the end of every poll
+/// and reported by the daemon's health checks.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+pub struct RunSummary {
+   /// Identifies the poll in the log, e.g., 5f0c2a91d4e7b368
+   pub run_id : String,
+   /// When the poll started (UTC seconds since epoch)
+   pub started : i64,
+   pub duration_ms : u64,
+   pub networks_fetched : Vec<String>,
+   /// Networks whose listing could not be fetched or parsed
+   pub networks_failed : Vec<String>,
+   /// Stations listed by SIS in the fetched networks
+   pub stations_seen : usize,
+   pub created : usize,
+   pub updated : usize,
+   /// Stored stations SIS no longer lists
+   pub removed : usize,
+   /// Stations to update whose row was gone
+   pub missing : usize,
+   /// Stations that could not be written
+   pub failed : usize,
+   /// sent, failed, held (for a digest, quiet hours, or maintenance), none
+   /// (nothing to send), or skipped (init or dry run)
+   pub notification : String,
+   /// Why the poll failed
+   pub error : Option<String>,
+}
+
+impl RunSummary {
+   pub fn to_json(&self) -> serde_json::Value {
+      serde_json::json!({"run_id": self.run_id,
+                         "started": self.started,
+                         "duration_ms": self.duration_ms,
+                         "networks_fetched": self.networks_fetched,
+                         "networks_failed": self.networks_failed,
+                         "stations_seen": self.stations_seen,
+                         "changes": {"created": self.created,
+                                     "updated": self.updated,
+                                     "removed": self.removed,
+                                     "missing": self.missing,
+                                     "failed": self.failed},
+                         "notification": self.notification,
+                         "error": self.error})
+   }
+}
@@ -0,0 +1,14 @@
+/// A change to a station that was recorded in the history table.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq, Eq)]
+pub struct StationChange {
+   /// The XML file name, e.g., UU_ALP.xml
+   pub station : String,
+   /// What happened, e.g., created, updated, or reset
+   pub action : String,
+   /// The SIS last modified time (UTC seconds since epoch)
+   pub time : i64,
+   /// When the poller recorded the change (UTC seconds since epoch)
+   pub detected : i64,
+}
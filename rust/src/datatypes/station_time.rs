@@ -1,13 +1,91 @@
-#[derive(Clone)]
-#[derive(Debug)]
+use chrono::{DateTime, Utc};
+
+/// A station XML file listed by SIS or stored in the database.  Stations are
+/// ordered by network, station, and file name, then by time.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct StationTime {
+   /// The network code, e.g., UU
+   pub network : String,
+   /// The station code, e.g., ALP
    pub station : String,
-   pub time : i64,
+   /// The XML file name, e.g., UU_ALP.xml
+   pub file_name : String,
+   /// The SIS last modified time
+   pub time : DateTime<Utc>,
+   /// Where SIS serves the file
+   pub url : String,
+   /// The size column of the SIS listing, e.g., 38K; None if the station
+   /// was read from the database
+   pub size : Option<String>,
 }
 
 impl StationTime {
-   #[allow(dead_code)]
-   fn new(station: String, time: i64) -> StationTime {
-      StationTime {station, time}
+   /// Creates a station from its XML file name, e.g., UU_ALP.xml, deriving
+   /// the network and station codes and the SIS URL.
+   pub fn new(file_name : &str, time : DateTime<Utc>) -> StationTime {
+      let stem = file_name.strip_suffix(".xml").unwrap_or(file_name);
+      let (network, station) = stem.split_once('_').unwrap_or(("", stem));
+      StationTime {network: network.to_string(),
+                   station: station.to_string(),
+                   file_name: file_name.to_string(),
+                   time,
+                   url: crate::source::file_url(network, file_name),
+                   size: None}
+   }
+
+   /// Creates a station from a time in UTC seconds since the epoch, as the
+   /// databases store it.
+   pub fn from_timestamp(file_name : &str, time : i64) -> Result<StationTime, Box<dyn std::error::Error>> {
+      let time = DateTime::from_timestamp(time, 0)
+                    .ok_or_else(|| format!("Invalid last modified time {} for {}", time, file_name))?;
+      Ok(StationTime::new(file_name, time))
+   }
+
+   /// Checks that the file name has the form NET_STA.xml and that the codes
+   /// and URL agree with it, e.g., for a station read from an export.
+   pub fn validate(&self) -> Result<(), String> {
+      let is_code = |code : &str| !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric());
+      let valid_name = self.file_name.strip_suffix(".xml")
+                                     .and_then(|stem| stem.split_once('_'))
+                                     .is_some_and(|(network, station)| is_code(network) && is_code(station));
+      if !valid_name {
+         return Err(format!("{} is not a station file name, e.g., UU_ALP.xml", self.file_name));
+      }
+      let expected = StationTime::new(&self.file_name, self.time);
+      for (field, value, expected) in [("network", &self.network, &expected.network),
+                                       ("station", &self.station, &expected.station),
+                                       ("url", &self.url, &expected.url)] {
+         if value != expected {
+            return Err(format!("{} has {} {} but the file name gives {}", self.file_name, field, value, expected));
+         }
+      }
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_new() {
+      let station = StationTime::from_timestamp("UU_ALP.xml", 1685438940).unwrap();
+      assert_eq!(station.network, "UU");
+      assert_eq!(station.station, "ALP");
+      assert_eq!(station.file_name, "UU_ALP.xml");
+      assert_eq!(station.url, "https://files.anss-sis.scsn.org/production/FDSNStationXML1.1/UU/UU_ALP.xml");
+      assert_eq!(station.time.timestamp(), 1685438940);
+      let json = serde_json::to_string(&station).unwrap();
+      assert_eq!(serde_json::from_str::<StationTime>(&json).unwrap(), station);
+      let later = StationTime::from_timestamp("UU_ALP.xml", 1685439000).unwrap();
+      let other = StationTime::from_timestamp("UU_BGU.xml", 0).unwrap();
+      assert!(station < later && later < other);
+      assert!(station.validate().is_ok());
+      assert!(StationTime::from_timestamp("UU-ALP.xml", 0).unwrap().validate().is_err());
+      assert!(StationTime::from_timestamp("UU_.xml", 0).unwrap().validate().is_err());
+      let mut moved = station.clone();
+      moved.network = String::from("WY");
+      assert!(moved.validate().is_err());
    }
 }
@@ -0,0 +1,64 @@
+//! Compares the SIS listing with the stored stations.
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::station_time::StationTime;
+
+/// Compares the SIS stations with the database.  A SIS station is added if it
+/// is not stored, updated if it was modified after its stored time, and
+/// otherwise unchanged.  A stored station is removed if SIS no longer lists it.
+pub fn diff(database_stations : &[StationTime],
+            sis_stations : &[StationTime],
+            fetched_networks : &[&str]) -> ChangeSet {
+   let mut changes = ChangeSet::new();
+   for sis_station in sis_stations.iter() {
+       match database_stations.iter().find(|e| e.file_name == sis_station.file_name) {
+          None => {
+             log::debug!("Candidate insert {}", sis_station.file_name);
+             changes.add(sis_station.clone());
+          }
+          Some(stored) if sis_station.time > stored.time => {
+             log::debug!("Candidate update {} {}", sis_station.file_name, sis_station.time);
+             changes.update(Some(stored.time), sis_station.clone());
+          }
+          Some(_) => changes.keep(sis_station.clone()),
+       }
+   }
+   // Only consider networks that were successfully fetched; otherwise a failed
+   // request would make every station in that network look removed.
+   for database_station in database_stations.iter() {
+       if !fetched_networks.contains(&database_station.network.as_str()) {
+          continue;
+       }
+       if !sis_stations.iter().any(|e| e.file_name == database_station.file_name) {
+          log::debug!("Candidate remove {}", database_station.file_name);
+          changes.remove(database_station.clone());
+       }
+   }
+   changes
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_diff() {
+      let database_stations = vec![StationTime::from_timestamp("UU_ALP.xml", 10).unwrap(),
+                                   StationTime::from_timestamp("UU_BGU.xml", 10).unwrap(),
+                                   StationTime::from_timestamp("UU_OLD.xml", 10).unwrap(),
+                                   StationTime::from_timestamp("WY_YHB.xml", 10).unwrap()];
+      let sis_stations = vec![StationTime::from_timestamp("UU_ALP.xml", 20).unwrap(),
+                              StationTime::from_timestamp("UU_BGU.xml", 10).unwrap(),
+                              StationTime::from_timestamp("UU_NEW.xml", 10).unwrap()];
+      let changes = diff(&database_stations, &sis_stations, &["UU"]);
+      let uu = &changes.networks["UU"];
+      assert_eq!(uu.added[0].file_name, "UU_NEW.xml");
+      assert_eq!(uu.updated[0].station.file_name, "UU_ALP.xml");
+      assert_eq!(uu.updated[0].old_time.unwrap().timestamp(), 10);
+      assert_eq!(uu.updated[0].new_time().timestamp(), 20);
+      assert_eq!(uu.unchanged[0].file_name, "UU_BGU.xml");
+      // WY was not fetched so its stations must not be reported as removed
+      assert_eq!(changes.removed().count(), 1);
+      assert_eq!(uu.removed[0].file_name, "UU_OLD.xml");
+      assert!(!changes.networks.contains_key("WY"));
+   }
+}
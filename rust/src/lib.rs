@@ -0,0 +1,26 @@
+//! Polls the Station Information System (SIS) for station updates.
+//!
+//! A poll is built from these steps, each of which can be used on its own:
+//!
+//! - [`source`] fetches the SIS listing of each network,
+//! - [`parser`] turns a listing into station modification times,
+//! - [`differ`] compares them with the stored stations,
+//! - [`database`] is the store, either sqlite3 or postgres,
+//! - [`notifier`] builds the change message and posts it to the API, and
+//! - [`poll`] runs the steps in order, once or as a daemon.
+//!
+//! [`snapshot`] exports the store to CSV or JSON and reads it back.
+//!
+//! The sisPoller binary is a command line interface over this library.
+pub mod configuration;
+pub mod database;
+pub mod datatypes;
+pub mod differ;
+pub mod logging;
+pub mod metrics;
+pub mod notifier;
+pub mod parser;
+pub mod poll;
+pub mod snapshot;
+pub mod source;
+pub mod status;
@@ -0,0 +1,166 @@
+use crate::datatypes::run_summary::RunSummary;
+
+/// The target of the summary event emitted at the end of every poll.
+pub static SUMMARY_TARGET: &str = "sis_poller::summary";
+
+/// How log records are written to stderr.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+   /// env_logger's human readable lines
+   Text,
+   /// One JSON object per line with stable field names
+   Json,
+}
+
+/// The poll currently running; added to every JSON record as run_id.
+static RUN_ID: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+fn run_id() -> Option<String> {
+   RUN_ID.lock().unwrap_or_else(|error| error.into_inner()).clone()
+}
+
+/// Starts a new run and returns its identifier.
+pub fn start_run() -> String {
+   let run_id = format!("{:016x}", rand::random::<u64>());
+   *RUN_ID.lock().unwrap_or_else(|error| error.into_inner()) = Some(run_id.clone());
+   run_id
+}
+
+pub fn end_run() {
+   *RUN_ID.lock().unwrap_or_else(|error| error.into_inner()) = None;
+}
+
+/// Collects a record's key-values, e.g., network and duration_ms, into JSON.
+struct JsonVisitor<'a> {
+   object : &'a mut serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonVisitor<'_> {
+   fn visit_pair(&mut self, key : log::kv::Key<'kvs>, value : log::kv::Value<'kvs>) -> Result<(), log::kv::Error> {
+      let value = if let Some(value) = value.to_bool() {
+         serde_json::json!(value)
+      }
+      else if let Some(value) = value.to_i64() {
+         serde_json::json!(value)
+      }
+      else if let Some(value) = value.to_u64() {
+         serde_json::json!(value)
+      }
+      else if let Some(value) = value.to_f64() {
+         serde_json::json!(value)
+      }
+      else {
+         serde_json::json!(value.to_string())
+      };
+      self.object.insert(key.as_str().to_string(), value);
+      Ok(())
+   }
+}
+
+/// Writes a record as one line of JSON with timestamp, level, target,
+/// run_id, message, and the record's key-values.
+pub fn write_json(buffer : &mut dyn std::io::Write, record : &log::Record) -> std::io::Result<()> {
+   let mut object = serde_json::Map::new();
+   object.insert("timestamp".to_string(),
+                 serde_json::json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+   object.insert("level".to_string(), serde_json::json!(record.level().as_str()));
+   object.insert("target".to_string(), serde_json::json!(record.target()));
+   let mut key_values = serde_json::Map::new();
+   let _ = record.key_values().visit(&mut JsonVisitor {object: &mut key_values});
+   // A record that carries its own run_id, e.g., the summary, is not given a second
+   match key_values.remove("run_id") {
+      Some(run_id) => {
+         object.insert("run_id".to_string(), run_id);
+      }
+      None => {
+         if let Some(run_id) = run_id() {
+            object.insert("run_id".to_string(), serde_json::json!(run_id));
+         }
+      }
+   }
+   object.insert("message".to_string(), serde_json::json!(record.args().to_string()));
+   object.extend(key_values);
+   writeln!(buffer, "{}", serde_json::Value::Object(object))
+}
+
+/// Initializes the logger.  RUST_LOG selects the level as before, except that
+/// the summary event is always shown unless RUST_LOG turns it off.
+pub fn init(format : LogFormat) {
+   let mut builder = env_logger::Builder::new();
+   builder.filter_level(log::LevelFilter::Error)
+          .filter_module(SUMMARY_TARGET, log::LevelFilter::Info)
+          .parse_default_env();
+   if format == LogFormat::Json {
+      builder.format(|buffer, record| write_json(buffer, record));
+   }
+   builder.init();
+}
+
+/// Emits the machine-readable summary of a poll.
+pub fn log_summary(summary : &RunSummary) {
+   let networks_fetched = summary.networks_fetched.join(",");
+   let networks_failed = summary.networks_failed.join(",");
+   let error = summary.error.as_deref().unwrap_or("");
+   log::info!(target: SUMMARY_TARGET,
+              run_id = summary.run_id.as_str(),
+              duration_ms = summary.duration_ms,
+              result = if summary.error.is_none() { "success" } else { "failure" },
+              networks_fetched = networks_fetched.as_str(),
+              networks_failed = networks_failed.as_str(),
+              stations_seen = summary.stations_seen,
+              created = summary.created,
+              updated = summary.updated,
+              removed = summary.removed,
+              missing = summary.missing,
+              failed = summary.failed,
+              notification = summary.notification.as_str(),
+              error = error;
+              "Poll summary");
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_write_json() {
+      let key_values : [(&str, log::kv::Value); 3] = [("network", "UU".into()),
+                                                      ("stations", 42u64.into()),
+                                                      ("duration_ms", 12.5f64.into())];
+      let record = log::Record::builder()
+                      .args(format_args!("Unpacked 42 stations for network UU"))
+                      .level(log::Level::Info)
+                      .target("sis_poller")
+                      .key_values(&key_values)
+                      .build();
+      let mut buffer : Vec<u8> = Vec::new();
+      write_json(&mut buffer, &record).unwrap();
+      let line = String::from_utf8(buffer).unwrap();
+      assert!(line.ends_with('\n'));
+      let value : serde_json::Value = serde_json::from_str(&line).unwrap();
+      assert_eq!(value["level"], "INFO");
+      assert_eq!(value["message"], "Unpacked 42 stations for network UU");
+      assert_eq!(value["network"], "UU");
+      assert_eq!(value["stations"], 42);
+      assert_eq!(value["duration_ms"], 12.5);
+   }
+
+   #[test]
+   fn test_write_json_run_id_once() {
+      let run_id = start_run();
+      let key_values : [(&str, log::kv::Value); 1] = [("run_id", run_id.as_str().into())];
+      let record = log::Record::builder()
+                      .args(format_args!("Poll summary"))
+                      .level(log::Level::Info)
+                      .target(SUMMARY_TARGET)
+                      .key_values(&key_values)
+                      .build();
+      let mut buffer : Vec<u8> = Vec::new();
+      write_json(&mut buffer, &record).unwrap();
+      end_run();
+      let line = String::from_utf8(buffer).unwrap();
+      assert_eq!(line.matches("\"run_id\"").count(), 1);
+      let value : serde_json::Value = serde_json::from_str(&line).unwrap();
+      assert_eq!(value["run_id"], run_id.as_str());
+   }
+}
@@ -1,34 +1,14 @@
-use clap::Parser;
-
-static DEFAULT_INI_FILE: &str = "./sisPoller.ini"; 
-
-/*
-#[derive(Clone)]
-#[derive(Debug)]
-struct StationTime {
-   station : String,
-   time : i64,
-}
-*/
-
-mod database;
-mod datatypes;
-use crate::datatypes::station_time::StationTime;
-
-#[derive(Clone)]
-struct Parameters {
-   sqlite3_file : String,
-   database_host : String,
-   database_port : i64,
-   database_name : String,
-   database_schema : String,
-   database_user : String,
-   database_password : String,
-   api_uri : String,
-   api_key : String,
-   api_notification_topic : String,
-   api_notification_type : String,
-}
+use clap::{Parser, Subcommand};
+
+use sis_poller::{database, logging, metrics, status};
+use sis_poller::configuration::{API_SECTION, ApiSection, Backend, Configuration, DEFAULT_INI_FILE};
+use sis_poller::datatypes::station_time::StationTime;
+use sis_poller::datatypes::station_change::StationChange;
+use sis_poller::datatypes::maintenance_window::MaintenanceWindow;
+use sis_poller::parser::{format_time, parse_duration, parse_string, parse_time};
+use sis_poller::poll::{record_history, run_daemon, run_poll};
+use sis_poller::snapshot::{ImportMode, Snapshot, SnapshotFormat};
+use sis_poller::status::{Failure, Status};
 
 #[derive(Parser)]
 #[command(name = "sisPoller")]
@@ -36,674 +16,499 @@ struct Parameters {
 #[command(about = "Polls the SIS XML page to detect station updates")]
 #[command(long_about = None)]
 struct CommandLineArguments {
-   #[arg(short, long, default_value = DEFAULT_INI_FILE)]
+   #[arg(short, long, global = true, env = "SIS_POLLER_INI_FILE", default_value = DEFAULT_INI_FILE)]
    ini_file: String,
-   #[arg(long, default_value_t = true)]
-   use_sqlite3: bool,
-   #[arg(long, default_value_t = false)]
-   initialize: bool, 
+   /// The storage backend; overrides the backend key in the [SISPoller] section
+   #[arg(short, long, global = true, value_enum)]
+   backend: Option<Backend>,
+   /// How log records are written; RUST_LOG still selects the level
+   #[arg(long, global = true, value_enum, env = "SIS_POLLER_LOG_FORMAT", default_value = "text")]
+   log_format: logging::LogFormat,
+   #[command(subcommand)]
+   command: Option<Command>,
 }
 
-/*
-fn get_stations_from_pg(connection_uri : &str, schema : &str) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   let mut stations : Vec<StationTime> = Vec::new();
-   let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-   if !schema.is_empty() {
-      let _ = client.execute("SET search_path TO production", &[])?;
-   }
-   for row in client.query("SELECT xml_file, EXTRACT(epoch FROM last_modified)::bigint AS last_modified FROM xml_update", &[])? {
-      let station : &str = row.get(0);
-      let time : i64 = row.get(1);
-      let pair = StationTime {station: station.to_string(), time: time};  
-      log::debug!("Database station {} {}", station, time);
-      stations.push(pair);
-   }
-   return Ok(stations);
+#[derive(Subcommand)]
+enum Command {
+   /// Seeds the database with the current SIS modification times
+   Init {
+      /// Fetch and diff against the database but do not write
+      #[arg(long, default_value_t = false)]
+      dry_run: bool,
+   },
+   /// Fetches SIS, updates the database, and posts any changes (default)
+   Poll {
+      /// Fetch and diff against the database but do not write or notify
+      #[arg(long, default_value_t = false)]
+      dry_run: bool,
+   },
+   /// Polls every poll_interval seconds and optionally serves /metrics, /healthz, and /readyz
+   Daemon,
+   /// Prints the stored stations and their last modified times
+   List {
+      /// Only list stations in this network, e.g., UU
+      #[arg(short, long)]
+      network: Option<String>,
+   },
+   /// Prints the stored state of one station, e.g., UU_ALP or UU.ALP
+   Show {
+      station: String,
+   },
+   /// Lists past changes
+   History {
+      /// Only list changes in this network, e.g., UU
+      #[arg(short, long)]
+      network: Option<String>,
+      /// Only list changes to this station, e.g., UU_ALP or UU.ALP
+      #[arg(short, long)]
+      station: Option<String>,
+      /// Only list the most recent changes
+      #[arg(short, long)]
+      limit: Option<usize>,
+   },
+   /// Applies any pending database schema migrations
+   Migrate,
+   /// Works with the ini file
+   Config {
+      #[command(subcommand)]
+      action: ConfigCommand,
+   },
+   /// Removes a station or network from the database so it is re-announced
+   Reset {
+      /// Remove every station in this network, e.g., UU
+      #[arg(short, long, required_unless_present = "station", conflicts_with = "station")]
+      network: Option<String>,
+      /// Remove this station, e.g., UU_ALP or UU.ALP
+      #[arg(short, long)]
+      station: Option<String>,
+   },
+   /// Writes the stored stations and history as CSV or JSON
+   Export {
+      /// The file to write; standard output if not given
+      #[arg(short, long)]
+      output: Option<String>,
+      /// Defaults to the output file's extension, or json
+      #[arg(short, long, value_enum)]
+      format: Option<SnapshotFormat>,
+      /// Leave out the history
+      #[arg(long, default_value_t = false)]
+      no_history: bool,
+   },
+   /// Loads stations and history written by export
+   Import {
+      /// The file to read; - reads standard input
+      file: String,
+      /// Defaults to the file's extension, or json
+      #[arg(short, long, value_enum)]
+      format: Option<SnapshotFormat>,
+      #[arg(short, long, value_enum, default_value = "merge")]
+      mode: ImportMode,
+      /// Validate the file without writing
+      #[arg(long, default_value_t = false)]
+      dry_run: bool,
+   },
+   /// Holds notifications during a maintenance window, e.g., mute --for 6h;
+   /// held changes are announced once it ends
+   Mute(MuteArguments),
 }
 
-fn get_stations_from_sqlite3(sqlite3_file : &str) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   // Make sure the sqlite3 file exists
-   create_xml_table_sqlite3(sqlite3_file);
-   //database::sqlite3::create_xml_table(sqlite3_file);
-   assert!(std::fs::exists(sqlite3_file).unwrap());
-   let mut stations : Vec<StationTime> = Vec::new();
-   let connection = rusqlite::Connection::open(sqlite3_file).unwrap(); 
-   let mut statement
-       = connection.prepare("SELECT xml_file, unixepoch(last_modified) AS last_modified FROM xml_update")?;
-   let station_iter = statement.query_map([], |row| {
-      Ok(StationTime {
-          station: row.get(0)?, 
-          time: row.get(1)?,
-        })
-   })?; 
-
-   for s in station_iter {
-      let station = s?;
-      log::debug!("Found station {:?} in sqlite3", station);
-      stations.push(station.clone());
-   }
-   return Ok(stations);
+#[derive(clap::Args)]
+struct MuteArguments {
+   /// When the window starts, e.g., 2026-10-20 08:00 (UTC); defaults to now
+   #[arg(long)]
+   from: Option<String>,
+   /// When the window ends
+   #[arg(long, required_unless_present_any = ["duration", "list", "clear"], conflicts_with = "duration")]
+   until: Option<String>,
+   /// How long the window lasts, e.g., 90m, 6h, or 2d
+   #[arg(long = "for", value_name = "DURATION")]
+   duration: Option<String>,
+   /// Why notifications are muted, e.g., a ticket
+   #[arg(long)]
+   reason: Option<String>,
+   /// List the current and future windows instead
+   #[arg(long, conflicts_with_all = ["from", "until", "duration", "reason", "clear"])]
+   list: bool,
+   /// Remove every window set with mute; held changes go out with the next poll
+   #[arg(long, conflicts_with_all = ["from", "until", "duration", "reason"])]
+   clear: bool,
 }
-*/
 
-/*
-fn create_xml_table_sqlite3(sqlite3_file : &str ) {
-   let sqlite_database_exists = std::fs::exists(sqlite3_file).unwrap();
-   if !sqlite_database_exists {
-      log::info!("Creating sqlite3 database {}", sqlite3_file);
-      let connection = rusqlite::Connection::open(sqlite3_file).unwrap();
-      connection.execute("CREATE TABLE xml_update (xml_file TEXT, last_modified TEXT)", (), ).unwrap();
-   }
-   else {
-      log::debug!("sqlite3 database {} already exists", sqlite3_file);
-   }
+#[derive(Subcommand)]
+enum ConfigCommand {
+   /// Validates the ini file and prints the effective settings with secrets redacted
+   Check,
 }
 
-fn create_stations_in_sqlite3(sqlite3_file : &str,
-                              stations_to_create : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   create_xml_table_sqlite3(sqlite3_file);
-   let mut created_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_create.is_empty() {
-      let connection = rusqlite::Connection::open(sqlite3_file).unwrap();
-      for station in stations_to_create.iter() {
-         let time : i64 = station.time as i64;
-         let result = connection.execute(
-             "INSERT INTO xml_update (xml_file, last_modified) VALUES(?1, DATETIME(?2, 'unixepoch'))",
-             (&station.station, &time), );
-         match result {
-            Ok(result) => {
-               log::debug!("Successful insert -> created {} row", &result);
-               created_stations.push(station.clone());
-            }
-            Err(result) => {
-               log::warn!("Insert failed -> {}", &result);
-            }
-         }
-      }
-      log::info!("Created {} out of {} stations in sqlite3 database", 
-                 created_stations.len(), stations_to_create.len());
-   }
-   else {
-      log::debug!("No stations to add to sqlite3");
+fn to_xml_file(station : &str) -> String {
+   // Accept UU_ALP.xml, UU_ALP, or UU.ALP
+   if station.ends_with(".xml") {
+      return station.to_string();
    }
-   return Ok(created_stations);
+   format!("{}.xml", station.replace('.', "_"))
 }
 
-fn update_stations_in_sqlite3(sqlite3_file : &str,
-                              stations_to_update : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   //assert!(std::fs::exists(sqlite3_file).unwrap()); // Has to exist
-   let mut updated_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_update.is_empty() {
-      let connection = rusqlite::Connection::open(sqlite3_file).unwrap();
-      for station in stations_to_update.iter() {
-         let time : i64 = station.time as i64;
-         let result = connection.execute(
-             "UPDATE xml_update SET last_modified TO DATETIME(?1, 'unixepoch') WHERE xml_file = ?2",
-             (&time, &station.station), );
-         match result {
-            Ok(result) => {
-               log::debug!("Successful updated -> station {} row", &result);
-               updated_stations.push(station.clone());
-            }
-            Err(result) => {
-               log::warn!("Insert failed -> {}", &result);
-            }
-         }
-      }
-      log::info!("Updated {} out of {} stations in sqlite3 database",
-                 updated_stations.len(), stations_to_update.len());
-   }
-   else {
-      log::debug!("No stations to add to sqlite3");
-   }
-   return Ok(updated_stations);
+fn in_network(xml_file : &str, network : &str) -> bool {
+   xml_file.starts_with(&format!("{}_", network))
 }
-*/
 
-/*
-fn create_stations_in_pg(connection_uri : &str, 
-                         schema : &str,
-                         stations_to_create : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   let mut created_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_create.is_empty() {
-       let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-       if !schema.is_empty() {
-          let _ = client.execute("SET search_path TO production", &[])?;
-       }
-       for station in stations_to_create.iter() {
-          let time : f64 = station.time as f64;
-          let result = client.execute(
-                       "INSERT INTO xml_update (xml_file, last_modified) VALUES($1, TO_TIMESTAMP($2))", 
-                       &[&station.station, &time],
-                       );
-          match result {
-             Ok(result) => {
-                log::debug!("Successful insert -> created {} row", &result);
-                created_stations.push(station.clone());
-             }   
-             Err(result) => {
-                log::warn!("Insert failed -> {}", &result);
-             }
-         }
+fn run_list(database : &mut database::Database,
+            network : &Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+   let mut stations = database.get_stations()?;
+   stations.sort();
+   for station in stations.iter() {
+      if let Some(network) = network
+         && station.network != *network {
+         continue;
       }
-      log::info!("Created {} out of {} stations in database", 
-                 created_stations.len(), stations_to_create.len());
+      println!("{} {}", station.file_name, format_time(station.time.timestamp()));
    }
-   return Ok(created_stations);
+   Ok(())
 }
 
-fn update_stations_in_pg(connection_uri : &str, 
-                         schema : &str,
-                         stations_to_update : &Vec<StationTime>) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
-   let mut updated_stations : Vec<StationTime> = Vec::new();
-   if !stations_to_update.is_empty() {
-       let mut client = postgres::Client::connect(connection_uri, postgres::NoTls)?;
-       if !schema.is_empty() {
-          let _ = client.execute("SET search_path TO production", &[])?;
-       }
-       for station in stations_to_update.iter() {
-          let time : f64 = station.time as f64;
-          let result = client.execute(
-                       "UPDATE xml_update SET last_modified = TO_TIMESTAMP($1) WHERE xml_file = $2", 
-                       &[&time, &station.station],
-                       );
-          match result {
-             Ok(result) => {
-                log::debug!("Successful update -> updated {} row", &result);
-                updated_stations.push(station.clone());
-             }
-             Err(result) => {
-                log::warn!("update failed -> {}", &result);
-             }
-         }
-      }   
-      log::info!("Updated {} out of {} stations in database", 
-                 updated_stations.len(), stations_to_update.len());
-   }   
-   return Ok(updated_stations);
+fn run_show(database : &mut database::Database,
+            station : &str) -> Result<(), Box<dyn std::error::Error>> {
+   let xml_file = to_xml_file(station);
+   let stations = database.get_stations()?;
+   let Some(stored) = stations.iter().find(|e| e.file_name == xml_file) else {
+      return Err(format!("Station {} not found in {} database", xml_file, database.name()).into());
+   };
+   println!("Station: {}", stored.file_name);
+   println!("Last modified: {}", format_time(stored.time.timestamp()));
+   let changes : Vec<StationChange>
+      = database.get_history()?.into_iter().filter(|e| e.station == xml_file).collect();
+   if let Some(last_change) = changes.last() {
+      println!("Last change: {} at {}", last_change.action, format_time(last_change.detected));
+   }
+   println!("Recorded changes: {}", changes.len());
+   Ok(())
 }
-*/
-
-fn post_to_api(uri : &str,
-               api_key : &str,
-               subject : &str,
-               message : &str,
-               topic : &str,
-               notification_type : &str,
-               message_identifier : &str) -> Result<String, Box<dyn std::error::Error>> {
-   //let source = format!("{:?}", gethostname::gethostname());
-   let source = String::from("rustSISPoller"); 
-   let payload
-       = serde_json::json!({
-            "payload": {"subject": subject,
-                        "message": message,
-                        "topic": topic,
-                        "notificationType": notification_type,
-                        "messageIdentifier": message_identifier,
-                        "source": source}
-                       });
-   log::debug!("Sending payload: {}", payload.to_string());
-   let client = reqwest::blocking::Client::new();
-   let response = client.put(uri)
-                 .header("x-api-key", api_key)
-                 .header("Content-Type", "application/json")
-                 .header("Accept", "application/json")
-                 .body(payload.to_string())
-                 .send()?;
 
-   if response.status() == 200 {
-      log::debug!("Successfully put to API");
-      let document_text = response.text()?.clone();
-      return Ok(document_text);
+fn run_history(database : &mut database::Database,
+               network : &Option<String>,
+               station : &Option<String>,
+               limit : Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+   let xml_file = station.as_deref().map(to_xml_file);
+   let changes : Vec<StationChange>
+      = database.get_history()?.into_iter().filter(|change| {
+           if let Some(network) = network
+              && !in_network(&change.station, network) {
+              return false;
+           }
+           if let Some(xml_file) = &xml_file
+              && change.station != *xml_file {
+              return false;
+           }
+           true
+        }).collect();
+   // Show the most recent changes when limiting
+   let start = match limit {
+      Some(limit) => changes.len().saturating_sub(limit),
+      None => 0,
+   };
+   for change in changes[start..].iter() {
+      println!("{} {} {} (modified {})",
+               format_time(change.detected), change.action, change.station, format_time(change.time));
    }
-   log::warn!("Errors detected while putting message to API");
-   return Err("Failed to post message".into());
-
+   Ok(())
 }
 
-fn create_email_message(stations_to_create : &Vec<StationTime>,
-                        stations_to_update : &Vec<StationTime>) -> String {
-   let mut result = String::from("");
-   if stations_to_create.is_empty() && stations_to_update.is_empty() {
-      return result;
+fn run_reset(database : &mut database::Database,
+             network : &Option<String>,
+             station : &Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+   let xml_file = station.as_deref().map(to_xml_file);
+   let stations_to_remove : Vec<StationTime>
+      = database.get_stations()?.into_iter().filter(|e| {
+           match (&xml_file, network) {
+              (Some(xml_file), _) => e.file_name == *xml_file,
+              (None, Some(network)) => e.network == *network,
+              (None, None) => false,
+           }
+        }).collect();
+   if stations_to_remove.is_empty() {
+      return Err(format!("No matching stations found in {} database", database.name()).into());
    }
-   for station in stations_to_create.iter() {
-      let create_string : String = format!("Added {}\n", station.station);
-      result.push_str(&create_string);
+   let result = database.remove_stations(&stations_to_remove)?;
+   record_history(database, "reset", &result.removed);
+   for station in result.removed.iter() {
+      println!("Reset {}", station.file_name);
    }
-   for station in stations_to_update.iter() {
-      let update_string : String = format!("Updated {}\n", station.station);
-      result.push_str(&update_string);
+   for station in result.missing.iter() {
+      println!("Missing {}; it was already gone", station.file_name);
    }
-   return result;
-}
-
-fn get_page(uri : &str) -> Result<String, Box<dyn std::error::Error>> {
-   let response = reqwest::blocking::get(uri)?;
-   // If I got a 200 code then return a win
-   if response.status() == 200 {
-      log::info!("Successfully hit URL");
-      let document_text = response.text()?.clone();
-      return Ok(document_text);
+   println!("Reset {} stations; they will be announced on the next poll", result.removed.len());
+   if !result.failed.is_empty() {
+      return Err(format!("Failed to reset {} stations in {} database", result.failed.len(), database.name()).into());
    }
-   return Err("No text to return".into());
-} 
-
-fn parse_string(timestamp : &str) -> i64 {
-   use chrono::{TimeZone, Utc};
-   let (year, month, day, hour, minute) = scan_fmt::scan_fmt!(timestamp, "{d}-{d}-{d} {d}:{d}", i32, u32, u32, u32, u32).unwrap();
-   let second : u32 = 0;
-   //println!("{} {} {} {} {} {}", year, month, day, hour, minute, second);
-   let d : chrono::DateTime<chrono::Utc>
-        = Utc.with_ymd_and_hms(year, month, day, hour, minute, second).unwrap();
-   let epochal_time : i64 = d.timestamp();
-   return epochal_time;
+   Ok(())
 }
 
-fn parse_page(document_text : &str,
-              network : &str,
-              keeper_stations : &Vec<&str>) -> Vec<StationTime> {
-   let mut stations : Vec<StationTime> = Vec::new();
-   // Initialize search string e.g., UU_
-   let mut search_string : String = network.to_string();
-   search_string.push_str("_");
-   // Parse the table
-   let table = table_extract::Table::find_first(&document_text.to_string()).unwrap();
-   for row in &table {
-       if row.is_empty() {
-          continue;
-       }
-       let row_slice = row.as_slice();
-       if row_slice.len() == 5 {
-          let text = row_slice.get(1).unwrap();//ok_or(&str, Ok(""));
-          // Does this row contain something like "UU_", e.g., "UU_ALP.xml"
-          if text.contains(&search_string) {
-             // Now let's parse the tag <a href="UU_ALP.xml">UU_ALP.xml></a>
-             let selector = scraper::Selector::parse(r#"a"#).unwrap();
-             let table_element_fragment = scraper::Html::parse_fragment(&text);
-             let station_anchor = table_element_fragment.select(&selector).next().unwrap();
-             let station_xml_file = station_anchor.inner_html().to_string();
-             let time = row_slice.get(2).unwrap(); 
-             let timestamp = parse_string(time);
-             let pair = StationTime {station: station_xml_file.clone(), time: timestamp};
-             let mut keep = false;
-             if !keeper_stations.is_empty() {
-                if keeper_stations.iter().any(|e| station_xml_file.contains(e)) {
-                   keep = true;
-                }
-             }
-             else {
-                keep = true;
-             }
-             if keep {         
-                stations.push(pair);
-             }
-             /*
-             for station in table_element_fragment.select(&selector) {
-                 let station_name = station.value().attr("href").expect("href not found").to_string();
-                 let time = row_slice.get(2).unwrap();
-                 let timestamp = parse_string(time); 
-                 let pair = StationTime {station: station_name.clone(), time: timestamp};
-                 stations.push(pair); 
-             }
-             */
-          }
-       }
+fn run_mute(database : &mut database::Database,
+            configuration : &Configuration,
+            arguments : &MuteArguments) -> Result<(), Box<dyn std::error::Error>> {
+   let now = chrono::Utc::now();
+   if arguments.list {
+      let mut windows : Vec<(MaintenanceWindow, &str)>
+         = configuration.maintenance.iter().map(|window| (window.clone(), "ini file")).collect();
+      windows.extend(database.get_maintenance()?.into_iter().map(|window| (window, "mute")));
+      windows.retain(|(window, _)| window.end > now);
+      windows.sort_by_key(|(window, _)| window.start);
+      for (window, source) in windows.iter() {
+         println!("{} to {}{} [{}]{}",
+                  format_time(window.start.timestamp()), format_time(window.end.timestamp()),
+                  if window.contains(now) { " (active)" } else { "" }, source,
+                  window.reason.as_ref().map(|e| format!(" {}", e)).unwrap_or_default());
+      }
+      if windows.is_empty() {
+         println!("No current or future maintenance windows");
+      }
+      return Ok(());
    }
-   return stations;
-}
-
-fn find_stations_to_create(database_stations : &Vec<StationTime>,
-                           sis_stations : &Vec<StationTime>) -> Vec<StationTime> {
-   // If there are no stations in the database then we create everything
-   if database_stations.is_empty() {
-       return sis_stations.clone();
+   if arguments.clear {
+      let count = database.clear_maintenance()?;
+      println!("Removed {} maintenance windows set with mute", count);
+      if configuration.maintenance.iter().any(|window| window.end > now) {
+         println!("Windows in the [Maintenance] section of {} still apply", configuration.file);
+      }
+      return Ok(());
    }
-   // Stations to be created do not exist in database
-   let mut result : Vec<StationTime> = Vec::new();
-   for sis_station in sis_stations.iter() {
-       let station_name = sis_station.station.to_string();
-       if !database_stations.iter().any(|e| station_name.contains(&e.station)) {
-          log::debug!("Candidate insert {}", station_name);
-          result.push(sis_station.clone());
-       }
+   let start = match &arguments.from {
+      Some(from) => parse_time(from)?,
+      None => now,
+   };
+   let end = match (&arguments.until, &arguments.duration) {
+      (Some(until), _) => parse_time(until)?,
+      (None, Some(duration)) => start + parse_duration(duration)?,
+      (None, None) => return Err("Give --until or --for".into()),
+   };
+   let window = MaintenanceWindow::new(start, end, arguments.reason.clone())?;
+   if window.end <= now {
+      return Err(format!("The window already ended at {}", format_time(window.end.timestamp())).into());
    }
-   return result;
+   database.add_maintenance(&window)?;
+   println!("Notifications are muted from {} to {}; changes detected meanwhile will be announced afterward",
+            format_time(window.start.timestamp()), format_time(window.end.timestamp()));
+   Ok(())
 }
 
-fn find_stations_to_update(database_stations : &Vec<StationTime>,
-                           sis_stations : &Vec<StationTime>) -> Vec<StationTime> {
-   // Stations to be updated exist in the database but have old load dates
-   let mut result : Vec<StationTime> = Vec::new();
-   for sis_station in sis_stations.iter() {
-       let station_name = sis_station.station.to_string();
-       let last_sis_update = sis_station.time;
-       if database_stations.iter().any(|e| station_name.contains(&e.station) && last_sis_update > e.time) {
-          log::debug!("Candidate update {} {}", station_name, last_sis_update);
-          result.push(sis_station.clone());
-       }
+fn run_export(database : &mut database::Database,
+              output : &Option<String>,
+              format : Option<SnapshotFormat>,
+              no_history : bool) -> Result<(), Box<dyn std::error::Error>> {
+   let format = format.or(output.as_deref().and_then(SnapshotFormat::from_file_name))
+                      .unwrap_or(SnapshotFormat::Json);
+   let mut stations = database.get_stations()?;
+   stations.sort();
+   let history = if no_history { Vec::new() } else { database.get_history()? };
+   let snapshot = Snapshot {stations, history};
+   let text = snapshot.write(format)?;
+   match output {
+      Some(output) => {
+         std::fs::write(output, text).map_err(|error| format!("Failed to write {}: {}", output, error))?;
+         eprintln!("Exported {} stations and {} history entries to {}",
+                   snapshot.stations.len(), snapshot.history.len(), output);
+      }
+      None => print!("{}", text),
    }
-   return result;
+   Ok(())
 }
 
-fn load_configuration(configuration_file : &String,
-                      use_sqlite3 : bool,
-                      initialize : bool) -> Result<Parameters, Box<dyn std::error::Error>> {
-   use configparser::ini::Ini;
-   let mut config = Ini::new();
-   let _map = config.load(configuration_file)?;
-
-   let mut sqlite3_file : String = String::from("./sisPoller.sqlite3");
-   let mut pg_database_host : String = String::from("localhost");
-   let mut pg_database_port : i64 = 5432;
-   let mut pg_database_name : String = String::from("");
-   let mut pg_database_schema : String = String::from("");
-   let mut pg_database_user : String = String::from("");
-   let mut pg_database_password : String = String::from(""); 
-   if use_sqlite3 {
-      let sqlite3_database_section = String::from("SISSqlite3Database");
-      let sqlite3_file_result = config.get(sqlite3_database_section.as_str(), "file_name");
-      match sqlite3_file_result {
-         Some(value) => sqlite3_file = value,
-         None => sqlite3_file = String::from("./sisPoller.sqlite3"),
-      }
+fn run_import(database : &mut database::Database,
+              file : &str,
+              format : Option<SnapshotFormat>,
+              mode : ImportMode,
+              dry_run : bool) -> Result<(), Box<dyn std::error::Error>> {
+   let text = if file == "-" {
+      std::io::read_to_string(std::io::stdin())?
    }
    else {
-      let pg_database_section = String::from("SISPostgresDatabase");
-      pg_database_host = config.get(pg_database_section.as_str(), "host").unwrap();
-      pg_database_port = config.getint(pg_database_section.as_str(), "port").unwrap().unwrap();
-      pg_database_name = config.get(pg_database_section.as_str(), "name").unwrap();
-      //let database_schema = config.get(pg_database_section.as_str(), "schema").unwrap();
-      //let database_schema : String;
-      let database_schema_result = config.get(pg_database_section.as_str(), "schema");
-      match database_schema_result {
-         Some(value) => pg_database_schema = value,
-         None => pg_database_schema = String::from(""),
-      } 
-      pg_database_user = config.get(pg_database_section.as_str(), "user").unwrap();
-      pg_database_password = config.get(pg_database_section.as_str(), "password").unwrap();
+      std::fs::read_to_string(file).map_err(|error| format!("Failed to read {}: {}", file, error))?
+   };
+   let format = format.or(SnapshotFormat::from_file_name(file)).unwrap_or(SnapshotFormat::Json);
+   let mut snapshot = Snapshot::read(&text, format)?;
+   if dry_run {
+      println!("Dry run - {} is valid with {} stations and {} history entries; nothing was written",
+               file, snapshot.stations.len(), snapshot.history.len());
+      return Ok(());
    }
-
-   let mut api_uri : String = String::from("");
-   let mut api_key : String = String::from("");
-   let mut api_notification_topic : String = String::from("production");
-   let mut api_notification_type : String = String::from("update_email");
-   if !initialize {
-      let api_section = String::from("AWSDistributionAPI");
-      api_uri = config.get(api_section.as_str(), "uri").unwrap();
-      api_key = config.get(api_section.as_str(), "key").unwrap();
-      let api_notification_topic_result = config.get(api_section.as_str(), "notificationTopic");
-      match api_notification_topic_result {
-         Some(value) => api_notification_topic = value,
-         None => api_notification_topic = String::from("production"),
+   let mut history = std::mem::take(&mut snapshot.history);
+   let result = match mode {
+      ImportMode::Replace => {
+         // The stored history is only replaced if the file has any
+         let replaced = database.replace_stations(&snapshot.stations,
+                                                  (!history.is_empty()).then_some(history.as_slice()))?;
+         println!("Removed {} stored stations", replaced.removed.len());
+         if !history.is_empty() {
+            println!("Removed {} stored history entries", replaced.history_removed);
+         }
+         if replaced.pending_removed > 0 {
+            println!("Dropped {} held notifications that no longer match a stored station", replaced.pending_removed);
+         }
+         replaced.upserted
       }
-
-      let api_notification_type_result = config.get(api_section.as_str(), "notificationType");
-      match api_notification_type_result {
-         Some(value) => api_notification_type = value,
-         None => api_notification_type = String::from("update_email"),
+      ImportMode::Merge => {
+         let stored = database.get_history()?;
+         history.retain(|change| !stored.contains(change));
+         let (stations, newer) = snapshot.merge_stations(&database.get_stations()?);
+         if !newer.is_empty() {
+            println!("Kept {} stored stations that are newer than the file", newer.len());
+         }
+         let mut result = database.upsert_stations(&stations)?;
+         result.unchanged.extend(newer);
+         database.add_history(&history)?;
+         result
       }
+   };
+   println!("Imported {} stations: {} inserted, {} updated, and {} unchanged",
+            snapshot.stations.len(), result.inserted.len(), result.updated.len(), result.unchanged.len());
+   println!("Imported {} history entries", history.len());
+   if !result.failed.is_empty() {
+      let failed : Vec<&str> = result.failed.iter().map(|e| e.file_name.as_str()).collect();
+      return Err(format!("Failed to import {} stations: {}", failed.len(), failed.join(", ")).into());
    }
-
-   let result = Parameters{
-                             sqlite3_file: sqlite3_file.to_string(),
-                             database_host: pg_database_host.to_string(),
-                             database_port: pg_database_port,
-                             database_name: pg_database_name.to_string(),
-                             database_schema: pg_database_schema.to_string(),
-                             database_user: pg_database_user.to_string(),
-                             database_password: pg_database_password.to_string(),
-                             api_uri: api_uri.to_string(),
-                             api_key: api_key.to_string(),
-                             api_notification_topic: api_notification_topic.to_string(),
-                             api_notification_type: api_notification_type.to_string(),
-                          };
-   return Ok(result);
+   Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-   // Import names from outer (for mod tests) scope)
-   use super::*;
-   //let ts = parse_string("2023-05-30 09:29");
-   assert_eq!(parse_string("2023-05-30 09:29"), 1685438940);
+fn run_migrate(database : &mut database::Database) -> Result<(), Box<dyn std::error::Error>> {
+   let applied = database.migrate()?;
+   for migration in applied.iter() {
+      println!("Applied migration {}: {}", migration.version, migration.description);
+   }
+   println!("The {} database schema is at version {}", database.name(), database.schema_version()?);
+   Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn run() -> Result<Status, Box<dyn std::error::Error>> {
    // Get command line arguments
    let command_line_arguments = CommandLineArguments::parse();
 
    // Initializing my logger
-   env_logger::init();
-
-   //let args: Vec<String> = std::env::args().collect();
-   //let ini_file : String = String::from("sisPoller.ini");
-   let parameters_result = load_configuration(&command_line_arguments.ini_file,
-                                              command_line_arguments.use_sqlite3,
-                                              command_line_arguments.initialize);
-   let parameters : Parameters;
-   match parameters_result {
-      Ok(result) => {
-         parameters = result.clone();
-      }   
+   logging::init(command_line_arguments.log_format);
+
+   // Without a subcommand behave like the original poller
+   let command = command_line_arguments.command.unwrap_or(Command::Poll { dry_run: false });
+   // Only polling posts so everything else can skip the API section
+   let api_section = match &command {
+      Command::Poll { dry_run: false } | Command::Daemon => ApiSection::Required,
+      Command::Config { .. } => ApiSection::IfPresent,
+      _ => ApiSection::Ignored,
+   };
+
+   let configuration_result = Configuration::load(&command_line_arguments.ini_file,
+                                                  command_line_arguments.backend,
+                                                  api_section);
+   let configuration : Configuration = match configuration_result {
+      Ok(result) => result,
       Err(error) => {
+         if let Command::Config { action: ConfigCommand::Check } = &command {
+            println!("{}", error);
+            return Ok(Status::ConfigurationError);
+         }
          log::warn!("Error loading parameters from initialization file: {error:?}");
-         return Err("Failed to load parameters from initialization file".into());
+         return Err(Failure::new(Status::ConfigurationError,
+                                 format!("Failed to load parameters from initialization file: {error}")).into());
       }
-   }
-
-   // Load the command line arguments
-   let database_connection_uri : String
-      = std::format!("postgresql://{}:{}@{}:{}/{}",
-                     parameters.database_user,
-                     parameters.database_password,
-                     parameters.database_host,
-                     parameters.database_port,
-                     parameters.database_name);
-
-
-   // Get the command line arguments
-   //let args: Vec<String> = std::env::args().collect();
-   // Lift the read-write database parameters
-   //let database_read_write_user = std::env::var("SIS_POLLER_DATABASE_READ_WRITE_USER")
-   //    .expect("Cannot find SIS_POLLER_DATABASE_READ_WRITE_USER environment variable");
-   //let database_read_write_password = std::env::var("SIS_POLLER_DATABASE_READ_WRITE_PASSWORD")
-   //    .expect("Cannot find SIS_POLLER_DATABASE_READ_WRITE_PASSWORD environent variable");
-   //let database_name = std::env::var("SIS_POLLER_DATABASE_NAME")
-   //    .expect("Cannot find SIS_POLLER_DATABASE_NAME environment variable");
-   //let database_host = std::env::var("SIS_POLLER_DATABASE_HOST")
-   //    .unwrap_or("localhost".to_string()); //expect("Cannot find SIS_POLLER_DATABASE_HOST environment variable");
-   //let database_port = std::env::var("SIS_POLLER_DATABASE_PORT")
-   //    .unwrap_or("5432".to_string());//expect("Cannot find SIS_POLLER_DATABASE_PORT environment variable");
-   //let database_schema = std::env::var("SIS_POLLER_DATABASE_SCHEMA")
-   //    .unwrap_or("".to_string());
-   //let database_connection_uri : String
-   //   = std::format!("postgresql://{}:{}@{}:{}/{}",
-   //                  database_read_write_user,
-   //                  database_read_write_password,
-   //                  database_host,
-   //                  database_port,
-   //                  database_name);
-   // Lift the API endpoint and key
-   //let notification_api_uri = std::env::var("SIS_NOTIFICATION_API_URI")
-   //   .expect("Cannot find SIS_NOTIFICATION_API_URI");
-   //let notification_api_key = std::env::var("SIS_NOTIFICATION_API_KEY")
-   //   .unwrap_or("".to_string()); //expect("Cannot find SIS_NOTIFICATION_API_KEY");
-   //let notification_topic = std::env::var("SIS_NOTIFICATION_API_TOPIC")
-   //   .unwrap_or("production".to_string()); // Can be production or test
-   //let notification_type = std::env::var("SIS_NOTIFICATION_API_TYPE")
-   //   .unwrap_or("update_email".to_string()); // Can be test_email or update_email
-   //let notification_topic : String = "test".to_string(); // Can be production or test
-   //let notification_type : String = "update_email".to_string(); // Can be test_email or update_email
+   };
 
    // Make sure I understand UTC time
-   let ts = parse_string("2023-05-30 09:29");
-   assert!(ts == 1685438940);
-
-   let database_stations : Vec<StationTime>;
-   if command_line_arguments.use_sqlite3 {
-      let database_stations_result
-         = database::sqlite3::get_stations(parameters.sqlite3_file.as_str());
-      match database_stations_result {
-         Ok(result) => {
-            database_stations = result.clone();
+   let ts = parse_string("2023-05-30 09:29")?;
+   assert!(ts.timestamp() == 1685438940);
+
+   // The database is opened lazily so a dry run never creates the sqlite3 file.
+   // Dry runs must not write so they never migrate.
+   let auto_migrate = match &command {
+      Command::Init { dry_run } | Command::Poll { dry_run } => configuration.auto_migrate && !*dry_run,
+      Command::Migrate => false,
+      _ => configuration.auto_migrate,
+   };
+   let mut database : database::Database = match (&configuration.sqlite3, &configuration.postgres) {
+      (Some(sqlite3), _) if configuration.backend == Backend::Sqlite3 => {
+         database::Database::sqlite3(&sqlite3.file_name, auto_migrate)
+      }
+      (_, Some(postgres)) => {
+         let connection = database::postgres::Connection::new(postgres)
+            .map_err(|error| Failure::new(Status::ConfigurationError, error.to_string()))?;
+         database::Database::postgres(connection, auto_migrate)
+      }
+      _ => return Err(Failure::new(Status::ConfigurationError, "No database configured").into()),
+   };
+
+   let metrics = std::sync::Arc::new(metrics::Metrics::new());
+
+   // Only one instance may write at a time; the daemon locks each poll itself
+   let writes = match &command {
+      Command::Init { dry_run } | Command::Poll { dry_run } => !*dry_run,
+      Command::Migrate | Command::Reset { .. } => true,
+      Command::Import { dry_run, .. } => !*dry_run,
+      Command::Mute(arguments) => !arguments.list,
+      _ => false,
+   };
+   let _lock = if writes {
+      let timeout = std::time::Duration::from_secs(configuration.lock_timeout);
+      match database.lock(timeout) {
+         Ok(Some(lock)) => Some(lock),
+         Ok(None) => {
+            return Err(Failure::new(Status::Locked,
+                                    format!("Another sis_poller instance is using the {} database", database.name())).into());
          }
          Err(error) => {
-            log::warn!("Error in getting database stations from sqlite3: {error:?}");
-            return Err("Failed getting database stations from database sqlite3".into());
+            log::warn!("Error locking {} database: {error:?}", database.name());
+            return Err(Failure::new(Status::StorageFailure,
+                                    format!("Failed to lock {} database: {error}", database.name())).into());
          }
-      }   
+      }
    }
    else {
-      log::info!("Fetching stations from postgres database");
-      let database_stations_result
-         = database::postgres::get_stations(database_connection_uri.as_str(),
-                                            parameters.database_schema.as_str());
-      match database_stations_result {
-         Ok(result) => {
-            database_stations = result.clone();
-         }
-         Err(error) => {
-            log::warn!("Error in getting database stations from postgres: {error:?}");
-            return Err("Failed getting database stations from postgres database".into());
+      None
+   };
+
+   let result = match &command {
+      Command::Init { dry_run } => return run_poll(&mut database, &configuration, true, *dry_run, &metrics),
+      Command::Poll { dry_run } => return run_poll(&mut database, &configuration, false, *dry_run, &metrics),
+      Command::Daemon => run_daemon(&mut database, &configuration, &metrics),
+      Command::Migrate => run_migrate(&mut database)
+         .map_err(|error| Failure::new(Status::StorageFailure, format!("Failed to migrate {} database: {error}", database.name())).into()),
+      Command::Config { action: ConfigCommand::Check } => {
+         print!("{}", configuration.redacted());
+         if configuration.api.is_none() {
+            println!("\nWarning: no [{}] section; poll and daemon need one to post notifications",
+                     API_SECTION);
          }
+         println!("\nConfiguration is valid");
+         Ok(())
       }
-   }
-
-   log::info!("Got {} stations from database", database_stations.len());
-
-
-   let base_uri = String::from("https://files.anss-sis.scsn.org/production/FDSNStationXML1.1/");
-   //let networks = vec!["UU"];
-   let networks = vec!["UU", "WY", "IW", "US", "C0", "NN"];
-   let iw_keeper_stations = vec!["FLWY", "IMW", "LOHW", "MOOW", "REDW", "RWWY", "SNOW", "TPAW"];
-   let us_keeper_stations = vec!["AHID", "BOZ", "BW06", "DUG",  "ELK",  "HLID", "HWUT", "ISCO", "LKWY", "MVCO", "TPNV", "WUAZ"];
-   let c0_keeper_stations = vec!["MOFF"];
-   let nn_keeper_stations = vec!["PIO", "V12A", "R11B", "PRN", "SHP", "WTNK", "SPR3", "Q12A"];
-   let mut sis_stations : Vec<StationTime> = Vec::new();
-   for network in networks.iter() {
-       let mut uri : String = base_uri.clone();
-       if !uri.ends_with('/') {
-          uri.push('/');
-       }
-       uri.push_str(network);
-       log::info!("Fetching data from URI: {}", uri);
-       let html_text_result = get_page(&uri);
-       match html_text_result {
-          Ok(html_text) => {
-             log::debug!("Parsing HTML...");
-             let mut keeper_stations : Vec<&str> = Vec::new();
-             if *network == "IW" {
-                keeper_stations = iw_keeper_stations.clone();
-             }
-             else if *network == "US" {
-                keeper_stations = us_keeper_stations.clone();
-             }
-             else if *network == "C0" {
-                keeper_stations = c0_keeper_stations.clone();
-             }
-             else if *network == "NN" {
-                keeper_stations = nn_keeper_stations.clone();
-             }
-             let stations = parse_page(&html_text, &network, &keeper_stations);
-             log::info!("Unpacked {} stations for network {}", stations.len(), network); 
-             sis_stations.extend(stations); 
-             //let new_stations = stations_to_create(&database_stations, &sis_stations); 
-          }
-          Err(error) => {
-             log::warn!("Error in getting HTML: {error:?}");
-             continue;
-          }
-       }
-   } 
-
-   log::debug!("Returned {} stations from SIS", sis_stations.len());
+      Command::List { network } => run_list(&mut database, network),
+      Command::Show { station } => run_show(&mut database, station),
+      Command::History { network, station, limit } => run_history(&mut database, network, station, *limit),
+      Command::Reset { network, station } => run_reset(&mut database, network, station),
+      Command::Export { output, format, no_history } => run_export(&mut database, output, *format, *no_history),
+      Command::Import { file, format, mode, dry_run } => run_import(&mut database, file, *format, *mode, *dry_run),
+      Command::Mute(arguments) => run_mute(&mut database, &configuration, arguments),
+   };
+   result.map(|_| Status::NoChanges)
+}
 
-   let candidate_stations_to_create = find_stations_to_create(&database_stations, &sis_stations);
-   log::info!("Will attempt to create {} stations", 
-              candidate_stations_to_create.len());
-   let stations_to_create : Vec<StationTime>;
-   if command_line_arguments.use_sqlite3 {
-      let stations_to_create_result
-          = database::sqlite3::create_stations(parameters.sqlite3_file.as_str(), 
-                                               &candidate_stations_to_create);
-      match stations_to_create_result {
-         Ok(result) => {
-            stations_to_create = result.clone();
-         }
-         Err(error) => {
-            log::warn!("Error adding stations to sqlite3: {error:?}");
-            return Err("Failed to add stations to sqlite3 database".into());
-         }   
+fn main() -> std::process::ExitCode {
+   match run() {
+      Ok(status) => std::process::ExitCode::from(status.code()),
+      Err(error) => {
+         eprintln!("Error: {}", error);
+         std::process::ExitCode::from(status::status_of(error.as_ref()).code())
       }
-      log::info!("Created {} stations in sqlite3", stations_to_create.len());
-   }
-   else {
-      stations_to_create
-          = database::postgres::create_stations(database_connection_uri.as_str(),
-                                                parameters.database_schema.as_str(),
-                                                &candidate_stations_to_create)?;
-      log::info!("Created {} stations in postgres", stations_to_create.len());
    }
+}
 
-   let candidate_stations_to_update = find_stations_to_update(&database_stations, &sis_stations);
-   log::info!("Will attempt to update {} stations", 
-              candidate_stations_to_update.len());
-
-   let stations_to_update : Vec<StationTime>;
-   if command_line_arguments.use_sqlite3 {
-      let stations_to_update_result
-          = database::sqlite3::update_stations(parameters.sqlite3_file.as_str(), 
-                                               &candidate_stations_to_update);
-      match stations_to_update_result {
-         Ok(result) => {
-            stations_to_update = result.clone();
-         }
-         Err(error) => {
-            log::warn!("Error updating stations to sqlite3: {error:?}");
-            return Err("Failed to add updating to sqlite3 database".into());
-         }
-      }   
-      log::info!("Updated {} stations in sqlite3", stations_to_update.len());
-   }
-   else {
-      stations_to_update
-         = database::postgres::update_stations(database_connection_uri.as_str(),
-                                               parameters.database_schema.as_str(),
-                                               &candidate_stations_to_update)?;
-      log::info!("Updated {} stations in postgres", stations_to_update.len());
-   }
+#[cfg(test)]
+mod tests {
+   // Import names from outer (for mod tests) scope)
+   use super::*;
 
-   if !command_line_arguments.initialize {
-      let message : String = create_email_message(&stations_to_create, &stations_to_update);
-      if !message.is_empty() {
-         let subject : String = "SIS poller notification".to_string();
-         let random_number : u32 = rand::random_range(0..=100000);
-         let message_identifier : String = "sisUpdateMessage_".to_string()
-                                         + &random_number.to_string(); // Could also be sisTestMessage
-         let post_result = post_to_api(&parameters.api_uri,
-                                       &parameters.api_key,
-                                       &subject,
-                                       &message,
-                                       &parameters.api_notification_topic,
-                                       &parameters.api_notification_type,
-                                       &message_identifier);
-         match post_result {
-            Ok(post_result) => {
-               log::info!("Succesfully put message to API {post_result:?}");
-            }
-            Err(error) => {
-               log::warn!("Failed to post message to API: {error:?}");
-               return Err("Failed to post message to API".into());
-            }
-         }
-      }
-      else {
-         log::info!("No updates detected");
-      }
+   #[test]
+   fn test_to_xml_file() {
+      assert_eq!(to_xml_file("UU_ALP.xml"), "UU_ALP.xml");
+      assert_eq!(to_xml_file("UU_ALP"), "UU_ALP.xml");
+      assert_eq!(to_xml_file("UU.ALP"), "UU_ALP.xml");
+      assert!(in_network("UU_ALP.xml", "UU"));
+      assert!(!in_network("UUX_ALP.xml", "UU"));
    }
-   else {
-      log::info!("Initialization mode - no updates posted to API");
-   }
-   Ok(())
 }
@@ -0,0 +1,152 @@
+pub mod server;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::datatypes::run_summary::RunSummary;
+
+/// The most recent fetch of one network's SIS listing.
+#[derive(Clone, Debug, Default)]
+struct NetworkFetch {
+   duration_seconds : f64,
+   http_status : u16,
+   stations : usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+   last_success : Option<i64>,
+   last_run : Option<RunSummary>,
+   polls : BTreeMap<&'static str, u64>,
+   networks : BTreeMap<String, NetworkFetch>,
+   changes : BTreeMap<&'static str, u64>,
+   removed : usize,
+   parse_errors : u64,
+   notification_failures : u64,
+   database_seconds : BTreeMap<&'static str, f64>,
+}
+
+/// Poll health in the Prometheus text exposition format.  Updated by each
+/// step of a poll and rendered by the metrics server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+   state : std::sync::Mutex<State>,
+}
+
+impl Metrics {
+   pub fn new() -> Metrics {
+      Metrics::default()
+   }
+
+   fn state(&self) -> std::sync::MutexGuard<'_, State> {
+      // A panic while holding the lock cannot leave the counters inconsistent
+      self.state.lock().unwrap_or_else(|error| error.into_inner())
+   }
+
+   /// Records one network fetch.  An HTTP status of 0 means no response.
+   pub fn record_fetch(&self,
+                       network : &str,
+                       duration : std::time::Duration,
+                       http_status : u16,
+                       stations : usize) {
+      self.state().networks.insert(network.to_string(),
+                                   NetworkFetch {duration_seconds: duration.as_secs_f64(), http_status, stations});
+   }
+
+   pub fn record_parse_error(&self) {
+      self.state().parse_errors += 1;
+   }
+
+   pub fn record_notification_failure(&self) {
+      self.state().notification_failures += 1;
+   }
+
+   /// Adds stations that were created or updated.
+   pub fn record_changes(&self, action : &'static str, count : usize) {
+      *self.state().changes.entry(action).or_insert(0) += count as u64;
+   }
+
+   /// Sets the number of stored stations SIS no longer lists.
+   pub fn record_removed(&self, count : usize) {
+      self.state().removed = count;
+   }
+
+   pub fn record_database(&self, operation : &'static str, duration : std::time::Duration) {
+      self.state().database_seconds.insert(operation, duration.as_secs_f64());
+   }
+
+   pub fn record_poll(&self, success : bool) {
+      let mut state = self.state();
+      *state.polls.entry(if success { "success" } else { "failure" }).or_insert(0) += 1;
+      if success {
+         state.last_success = Some(chrono::Utc::now().timestamp());
+      }
+   }
+
+   pub fn record_run(&self, summary : RunSummary) {
+      self.state().last_run = Some(summary);
+   }
+
+   pub fn last_success(&self) -> Option<i64> {
+      self.state().last_success
+   }
+
+   pub fn last_run(&self) -> Option<RunSummary> {
+      self.state().last_run.clone()
+   }
+
+   pub fn render(&self) -> String {
+      let state = self.state();
+      let mut text = String::new();
+      let mut metric = |name : &str, kind : &str, help : &str, samples : Vec<(String, String)>| {
+         let _ = writeln!(text, "# HELP {} {}", name, help);
+         let _ = writeln!(text, "# TYPE {} {}", name, kind);
+         for (labels, value) in samples {
+            let _ = writeln!(text, "{}{} {}", name, labels, value);
+         }
+      };
+      metric("sis_poller_last_success_timestamp_seconds", "gauge",
+             "Unix time of the last successful poll",
+             state.last_success.iter().map(|time| (String::new(), time.to_string())).collect());
+      metric("sis_poller_polls_total", "counter", "Completed polls by result",
+             state.polls.iter().map(|(result, count)| (format!("{{result=\"{}\"}}", result), count.to_string())).collect());
+      metric("sis_poller_fetch_duration_seconds", "gauge", "Duration of the last fetch of each network",
+             state.networks.iter().map(|(network, fetch)| (format!("{{network=\"{}\"}}", network), fetch.duration_seconds.to_string())).collect());
+      metric("sis_poller_fetch_http_status", "gauge", "HTTP status of the last fetch of each network; 0 if there was no response",
+             state.networks.iter().map(|(network, fetch)| (format!("{{network=\"{}\"}}", network), fetch.http_status.to_string())).collect());
+      metric("sis_poller_fetch_stations", "gauge", "Stations parsed from the last fetch of each network",
+             state.networks.iter().map(|(network, fetch)| (format!("{{network=\"{}\"}}", network), fetch.stations.to_string())).collect());
+      metric("sis_poller_changes_total", "counter", "Stations created or updated in the database",
+             state.changes.iter().map(|(action, count)| (format!("{{action=\"{}\"}}", action), count.to_string())).collect());
+      metric("sis_poller_removed_stations", "gauge", "Stored stations no longer listed by SIS in the last poll",
+             vec![(String::new(), state.removed.to_string())]);
+      metric("sis_poller_parse_errors_total", "counter", "SIS listings that could not be parsed",
+             vec![(String::new(), state.parse_errors.to_string())]);
+      metric("sis_poller_notification_failures_total", "counter", "Notifications the API did not accept",
+             vec![(String::new(), state.notification_failures.to_string())]);
+      metric("sis_poller_database_duration_seconds", "gauge", "Duration of the last database operation of each kind",
+             state.database_seconds.iter().map(|(operation, seconds)| (format!("{{operation=\"{}\"}}", operation), seconds.to_string())).collect());
+      text
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_render() {
+      let metrics = Metrics::new();
+      metrics.record_fetch("UU", std::time::Duration::from_millis(1500), 200, 42);
+      metrics.record_changes("created", 2);
+      metrics.record_changes("created", 1);
+      metrics.record_parse_error();
+      metrics.record_poll(true);
+      let text = metrics.render();
+      assert!(text.contains("sis_poller_fetch_duration_seconds{network=\"UU\"} 1.5\n"));
+      assert!(text.contains("sis_poller_fetch_http_status{network=\"UU\"} 200\n"));
+      assert!(text.contains("sis_poller_fetch_stations{network=\"UU\"} 42\n"));
+      assert!(text.contains("sis_poller_changes_total{action=\"created\"} 3\n"));
+      assert!(text.contains("sis_poller_parse_errors_total 1\n"));
+      assert!(text.contains("sis_poller_polls_total{result=\"success\"} 1\n"));
+      assert!(text.contains("sis_poller_last_success_timestamp_seconds "));
+   }
+}
@@ -0,0 +1,97 @@
+use crate::database::Probe;
+use crate::metrics::Metrics;
+
+/// Decides whether the poller is ready: the store must be reachable and the
+/// last successful poll no older than max_age.
+pub struct Readiness {
+   pub probe : Probe,
+   pub max_age : std::time::Duration,
+}
+
+fn json_response(status : u16, body : serde_json::Value) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+   let header = tiny_http::Header::from_bytes("Content-Type", "application/json")
+                   .expect("Content-Type header is valid");
+   tiny_http::Response::from_string(body.to_string()).with_status_code(status).with_header(header)
+}
+
+fn health(metrics : &Metrics) -> serde_json::Value {
+   serde_json::json!({"status": "up",
+                      "last_success": metrics.last_success(),
+                      "last_run": metrics.last_run().map(|summary| summary.to_json())})
+}
+
+fn readiness(metrics : &Metrics, readiness : &Readiness) -> (u16, serde_json::Value) {
+   let mut problems : Vec<String> = Vec::new();
+   if let Err(error) = readiness.probe.check() {
+      problems.push(format!("database is unreachable: {}", error));
+   }
+   let now = chrono::Utc::now().timestamp();
+   match metrics.last_success() {
+      Some(time) if now - time <= readiness.max_age.as_secs() as i64 => {}
+      Some(time) => problems.push(format!("last successful poll was {} seconds ago", now - time)),
+      None => problems.push("no poll has succeeded yet".to_string()),
+   }
+   let mut body = health(metrics);
+   body["status"] = serde_json::json!(if problems.is_empty() { "ready" } else { "not ready" });
+   body["problems"] = serde_json::json!(problems);
+   (if problems.is_empty() { 200 } else { 503 }, body)
+}
+
+/// Serves /metrics, /healthz, and /readyz on a background thread for the
+/// life of the process.
+pub fn start(address : &str,
+             metrics : std::sync::Arc<Metrics>,
+             ready : Readiness) -> Result<(), Box<dyn std::error::Error>> {
+   let server = tiny_http::Server::http(address)
+      .map_err(|error| format!("Failed to listen on {}: {}", address, error))?;
+   log::info!("Serving metrics and health checks on http://{}", address);
+   std::thread::spawn(move || {
+      for request in server.incoming_requests() {
+         let response = match request.url() {
+            "/metrics" => {
+               let header = tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                               .expect("Content-Type header is valid");
+               tiny_http::Response::from_string(metrics.render()).with_header(header)
+            }
+            "/healthz" => json_response(200, health(&metrics)),
+            "/readyz" => {
+               let (status, body) = readiness(&metrics, &ready);
+               json_response(status, body)
+            }
+            _ => tiny_http::Response::from_string("Not found\n").with_status_code(404),
+         };
+         if let Err(error) = request.respond(response) {
+            log::warn!("Failed to respond to HTTP request: {}", error);
+         }
+      }
+   });
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_readiness() {
+      let file = std::env::temp_dir().join(format!("sis_poller_ready_{}.sqlite3", std::process::id()));
+      let _ = std::fs::remove_file(&file);
+      let metrics = Metrics::new();
+      let ready = Readiness {probe: Probe::Sqlite3(file.to_str().unwrap().to_string()),
+                             max_age: std::time::Duration::from_secs(60)};
+      let (status, body) = readiness(&metrics, &ready);
+      assert_eq!(status, 503);
+      assert_eq!(body["problems"].as_array().unwrap().len(), 2);
+
+      crate::database::Database::sqlite3(file.to_str().unwrap(), true).get_stations().unwrap();
+      metrics.record_poll(true);
+      metrics.record_run(crate::datatypes::run_summary::RunSummary {networks_fetched: vec!["UU".to_string()],
+                                                                    stations_seen: 3,
+                                                                    ..Default::default()});
+      let (status, body) = readiness(&metrics, &ready);
+      assert_eq!(status, 200);
+      assert_eq!(body["last_run"]["networks_fetched"][0], "UU");
+      assert_eq!(body["last_run"]["stations_seen"], 3);
+      std::fs::remove_file(&file).unwrap();
+   }
+}
@@ -0,0 +1,76 @@
+//! Builds the change notification and posts it to the API.
+pub mod routing;
+pub mod template;
+
+/// Puts a notification to the API and returns the API's response.
+pub fn post_to_api(uri : &str,
+                   api_key : &str,
+                   subject : &str,
+                   message : &str,
+                   topic : &str,
+                   notification_type : &str,
+                   message_identifier : &str) -> Result<String, Box<dyn std::error::Error>> {
+   //let source = format!("{:?}", gethostname::gethostname());
+   let source = String::from("rustSISPoller"); 
+   let payload
+       = serde_json::json!({
+            "payload": {"subject": subject,
+                        "message": message,
+                        "topic": topic,
+                        "notificationType": notification_type,
+                        "messageIdentifier": message_identifier,
+                        "source": source}
+                       });
+   log::debug!("Sending payload: {}", payload);
+   let client = reqwest::blocking::Client::new();
+   let response = client.put(uri)
+                 .header("x-api-key", api_key)
+                 .header("Content-Type", "application/json")
+                 .header("Accept", "application/json")
+                 .body(payload.to_string())
+                 .send()?;
+
+   if response.status() == 200 {
+      log::debug!("Successfully put to API");
+      let document_text = response.text()?.clone();
+      return Ok(document_text);
+   }
+   log::warn!("Errors detected while putting message to API");
+   Err("Failed to post message".into())
+
+}
+
+/// Describes a duration for people, e.g., 3 days 4 hours, keeping the two
+/// largest units.
+pub fn format_duration(seconds : i64) -> String {
+   let units = [("day", 86400), ("hour", 3600), ("minute", 60), ("second", 1)];
+   let mut remaining = seconds.max(0);
+   let mut parts : Vec<String> = Vec::new();
+   for (name, size) in units.iter() {
+      let count = remaining / size;
+      remaining %= size;
+      if count > 0 {
+         parts.push(format!("{} {}{}", count, name, if count == 1 { "" } else { "s" }));
+      }
+      if parts.len() == 2 {
+         break;
+      }
+   }
+   if parts.is_empty() {
+      return String::from("0 seconds");
+   }
+   parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_format_duration() {
+      assert_eq!(format_duration(0), "0 seconds");
+      assert_eq!(format_duration(59), "59 seconds");
+      assert_eq!(format_duration(3600 + 61), "1 hour 1 minute");
+      assert_eq!(format_duration(3 * 86400 + 7200 + 5), "3 days 2 hours");
+   }
+}
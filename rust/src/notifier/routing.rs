@@ -0,0 +1,164 @@
+use crate::configuration::{ApiParameters, Configuration, RouteParameters};
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::station_time::StationTime;
+use crate::notifier::template::Templates;
+
+/// Where part of a change set is announced.  The default route takes the
+/// stations no configured route matches.
+pub struct Route {
+   pub name : String,
+   pub templates : Templates,
+   parameters : Option<RouteParameters>,
+}
+
+/// A rendered notification and the route it is posted to.
+pub struct Notification<'a> {
+   pub route : &'a Route,
+   pub subject : String,
+   pub message : String,
+}
+
+/// Matches text against a pattern in which * matches any run of characters.
+fn glob_match(pattern : &str, text : &str) -> bool {
+   match pattern.split_once('*') {
+      None => pattern.eq_ignore_ascii_case(text),
+      Some((prefix, rest)) => {
+         if text.len() < prefix.len() || !text[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            return false;
+         }
+         let text = &text[prefix.len()..];
+         (0..=text.len()).filter(|i| text.is_char_boundary(*i)).any(|i| glob_match(rest, &text[i..]))
+      }
+   }
+}
+
+/// True if the station matches one of the patterns, e.g., US.BOZ or NN.*.
+pub fn matches_station(patterns : &[String], station : &StationTime) -> bool {
+   let code = format!("{}.{}", station.network, station.station);
+   patterns.iter().any(|pattern| glob_match(pattern, &code))
+}
+
+impl Route {
+   /// The default route followed by every configured route.
+   pub fn load(configuration : &Configuration) -> Result<Vec<Route>, Box<dyn std::error::Error>> {
+      let mut routes = vec![Route {name: String::from("default"),
+                                   templates: Templates::load(&configuration.notification)?,
+                                   parameters: None}];
+      for parameters in configuration.routes.iter() {
+         routes.push(Route {name: parameters.name.clone(),
+                            templates: Templates::load(&parameters.notification)
+                               .map_err(|error| format!("Route {}: {}", parameters.name, error))?,
+                            parameters: Some(parameters.clone())});
+      }
+      Ok(routes)
+   }
+
+   pub fn is_default(&self) -> bool {
+      self.parameters.is_none()
+   }
+
+   /// True if the station is in one of the route's networks or matches one of
+   /// its station patterns, e.g., US.BOZ or NN.*.
+   pub fn matches(&self, station : &StationTime) -> bool {
+      let Some(parameters) = &self.parameters else {
+         return false;
+      };
+      parameters.networks.iter().any(|network| glob_match(network, &station.network))
+         || matches_station(&parameters.stations, station)
+   }
+
+   pub fn uri<'a>(&'a self, api : &'a ApiParameters) -> &'a str {
+      self.parameters.as_ref().and_then(|e| e.uri.as_deref()).unwrap_or(&api.uri)
+   }
+
+   pub fn key<'a>(&'a self, api : &'a ApiParameters) -> &'a str {
+      self.parameters.as_ref().and_then(|e| e.key.as_deref()).unwrap_or(&api.key)
+   }
+
+   pub fn notification_topic<'a>(&'a self, api : &'a ApiParameters) -> &'a str {
+      self.parameters.as_ref().and_then(|e| e.notification_topic.as_deref()).unwrap_or(&api.notification_topic)
+   }
+
+   pub fn notification_type<'a>(&'a self, api : &'a ApiParameters) -> &'a str {
+      self.parameters.as_ref().and_then(|e| e.notification_type.as_deref()).unwrap_or(&api.notification_type)
+   }
+}
+
+/// Splits the change set by route.  A station goes to every route that
+/// matches it, or to the default route if none does.  Routes without added or
+/// updated stations are left out.
+pub fn split<'a>(changes : &ChangeSet, routes : &'a [Route]) -> Vec<(&'a Route, ChangeSet)> {
+   let configured : Vec<&Route> = routes.iter().filter(|route| !route.is_default()).collect();
+   routes.iter().map(|route| {
+      let routed = if route.is_default() {
+         changes.filter(|station| !configured.iter().any(|e| e.matches(station)))
+      }
+      else {
+         changes.filter(|station| route.matches(station))
+      };
+      (route, routed)
+   }).filter(|(_, routed)| routed.has_changes()).collect()
+}
+
+/// Renders the notification of each route that has something to announce.
+pub fn notifications<'a>(changes : &ChangeSet,
+                         routes : &'a [Route]) -> Result<Vec<Notification<'a>>, minijinja::Error> {
+   let mut result : Vec<Notification> = Vec::new();
+   for (route, routed) in split(changes, routes) {
+      let (subject, message) = route.templates.render(&routed)?;
+      result.push(Notification {route, subject, message});
+   }
+   Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::configuration::{NotificationFormat, NotificationParameters};
+
+   fn route(name : &str, networks : &[&str], stations : &[&str]) -> Route {
+      let notification = NotificationParameters {format: NotificationFormat::Text, subject_template: None, body_template: None};
+      Route {name: name.to_string(),
+             templates: Templates::default(),
+             parameters: Some(RouteParameters {name: name.to_string(),
+                                               networks: networks.iter().map(|e| e.to_string()).collect(),
+                                               stations: stations.iter().map(|e| e.to_string()).collect(),
+                                               uri: None,
+                                               key: None,
+                                               notification_topic: Some(name.to_string()),
+                                               notification_type: None,
+                                               notification})}
+   }
+
+   #[test]
+   fn test_split() {
+      assert!(glob_match("NN.*", "NN.PIO"));
+      assert!(glob_match("*.BOZ", "US.BOZ"));
+      assert!(glob_match("us.boz", "US.BOZ"));
+      assert!(!glob_match("US.BOZ", "US.BOZA"));
+
+      let station = |file_name : &str| StationTime::from_timestamp(file_name, 10).unwrap();
+      let mut changes = ChangeSet::new();
+      for file_name in ["UU_ALP.xml", "IW_FLWY.xml", "US_BOZ.xml", "US_AHID.xml", "NN_PIO.xml"] {
+         changes.add(station(file_name));
+      }
+      let mut default = route("default", &[], &[]);
+      default.parameters = None;
+      let routes = vec![default, route("keepers", &["IW"], &["US.BOZ", "NN.*"]), route("us", &["US"], &[])];
+      let split = split(&changes, &routes);
+      let names : Vec<Vec<&str>> = split.iter()
+         .map(|(_, routed)| routed.added().map(|e| e.file_name.as_str()).collect())
+         .collect();
+      assert_eq!(names, vec![vec!["UU_ALP.xml"],
+                             vec!["IW_FLWY.xml", "NN_PIO.xml", "US_BOZ.xml"],
+                             vec!["US_BOZ.xml", "US_AHID.xml"]]);
+
+      let api = ApiParameters {uri: String::from("https://example"), key: String::from("k"),
+                               notification_topic: String::from("production"),
+                               notification_type: String::from("update_email")};
+      assert_eq!(split[0].0.notification_topic(&api), "production");
+      assert_eq!(split[1].0.notification_topic(&api), "keepers");
+      assert_eq!(split[1].0.notification_type(&api), "update_email");
+      assert_eq!(notifications(&changes.filter(|e| e.network == "UU"), &routes).unwrap().len(), 1);
+   }
+}
@@ -0,0 +1,178 @@
+use crate::configuration::{NotificationFormat, NotificationParameters};
+use crate::datatypes::change_set::ChangeSet;
+use crate::datatypes::station_time::StationTime;
+use crate::notifier::format_duration;
+use crate::parser::format_time;
+
+static SUBJECT: &str = include_str!("templates/subject.txt");
+static TEXT_BODY: &str = include_str!("templates/body.txt");
+static MARKDOWN_BODY: &str = include_str!("templates/body.md");
+static HTML_BODY: &str = include_str!("templates/body.html");
+
+/// The subject and body templates of a notification.  Templates use the
+/// minijinja (Jinja2) syntax; see the built-in templates in
+/// src/notifier/templates for the variables they can use.
+pub struct Templates {
+   environment : minijinja::Environment<'static>,
+}
+
+fn station(station : &StationTime) -> serde_json::Value {
+   serde_json::json!({"network": station.network,
+                      "station": station.station,
+                      "file_name": station.file_name,
+                      "url": station.url,
+                      "size": station.size,
+                      "time": format_time(station.time.timestamp())})
+}
+
+fn counts(added : usize, updated : usize) -> String {
+   let mut counts : Vec<String> = Vec::new();
+   if added > 0 {
+      counts.push(format!("{} added", added));
+   }
+   if updated > 0 {
+      counts.push(format!("{} updated", updated));
+   }
+   counts.join(", ")
+}
+
+/// The variables available to templates: counts, the networks with added or
+/// updated stations, and the whole change set as changes.
+fn context(changes : &ChangeSet) -> serde_json::Value {
+   let networks : Vec<serde_json::Value> = changes.networks.iter()
+      .filter(|(_, network)| !network.added.is_empty() || !network.updated.is_empty())
+      .map(|(name, network)| {
+         let updated : Vec<serde_json::Value> = network.updated.iter().map(|update| {
+            let mut value = station(&update.station);
+            value["old_time"] = serde_json::json!(update.old_time.map(|time| format_time(time.timestamp())));
+            value["since"] = serde_json::json!(update.old_time.map(|time| format_duration((update.new_time() - time).num_seconds())));
+            value
+         }).collect();
+         serde_json::json!({"name": name,
+                            "counts": counts(network.added.len(), network.updated.len()),
+                            "changed": network.added.len() + network.updated.len(),
+                            "bulk": network.bulk,
+                            "added": network.added.iter().map(station).collect::<Vec<_>>(),
+                            "updated": updated,
+                            "removed": network.removed.iter().map(station).collect::<Vec<_>>()})
+      }).collect();
+   serde_json::json!({"counts": counts(changes.added().count(), changes.updated().count()),
+                      "added": changes.added().count(),
+                      "updated": changes.updated().count(),
+                      "removed": changes.removed().count(),
+                      "networks": networks,
+                      "changes": changes.to_json()})
+}
+
+impl Templates {
+   /// Compiles the templates.  None selects the built-in template, and the
+   /// format selects the built-in body and whether values are HTML escaped.
+   pub fn new(subject : Option<String>,
+              body : Option<String>,
+              format : NotificationFormat) -> Result<Templates, minijinja::Error> {
+      let mut environment = minijinja::Environment::new();
+      environment.set_trim_blocks(true);
+      environment.set_lstrip_blocks(true);
+      environment.set_keep_trailing_newline(true);
+      environment.set_auto_escape_callback(move |_| {
+         if format == NotificationFormat::Html { minijinja::AutoEscape::Html } else { minijinja::AutoEscape::None }
+      });
+      let default_body = match format {
+         NotificationFormat::Text => TEXT_BODY,
+         NotificationFormat::Markdown => MARKDOWN_BODY,
+         NotificationFormat::Html => HTML_BODY,
+      };
+      environment.add_template_owned("subject", subject.unwrap_or(SUBJECT.to_string()))?;
+      environment.add_template_owned("body", body.unwrap_or(default_body.to_string()))?;
+      Ok(Templates {environment})
+   }
+
+   /// Reads and compiles the templates named in the configuration.
+   pub fn load(parameters : &NotificationParameters) -> Result<Templates, Box<dyn std::error::Error>> {
+      let read = |file_name : &Option<String>| -> Result<Option<String>, Box<dyn std::error::Error>> {
+         match file_name {
+            Some(file_name) => Ok(Some(std::fs::read_to_string(file_name)
+                                          .map_err(|error| format!("Failed to read template {}: {}", file_name, error))?)),
+            None => Ok(None),
+         }
+      };
+      Ok(Templates::new(read(&parameters.subject_template)?, read(&parameters.body_template)?, parameters.format)?)
+   }
+
+   /// Renders the subject, as one line, and the body.
+   pub fn render(&self, changes : &ChangeSet) -> Result<(String, String), minijinja::Error> {
+      let context = minijinja::Value::from_serialize(context(changes));
+      let subject = self.environment.get_template("subject")?.render(&context)?;
+      let body = self.environment.get_template("body")?.render(&context)?;
+      Ok((subject.split_whitespace().collect::<Vec<_>>().join(" "), body))
+   }
+}
+
+impl Default for Templates {
+   fn default() -> Templates {
+      Templates::new(None, None, NotificationFormat::Text).expect("built-in templates compile")
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn changes() -> ChangeSet {
+      let station = |file_name : &str, time : i64| StationTime::from_timestamp(file_name, time).unwrap();
+      let mut changes = ChangeSet::new();
+      changes.add(station("UU_NEW.xml", 1685438940));
+      changes.update(Some(station("UU_ALP.xml", 1685438940 - 3 * 86400 - 7200).time),
+                     station("UU_ALP.xml", 1685438940));
+      changes.update(None, station("WY_YHB.xml", 1685438940));
+      changes.remove(station("NN_OLD.xml", 0));
+      changes
+   }
+
+   #[test]
+   fn test_default_templates() {
+      let (subject, body) = Templates::default().render(&changes()).unwrap();
+      assert_eq!(subject, "SIS poller notification");
+      assert_eq!(body,
+                 "SIS changes in 2 networks: 1 added, 2 updated\n\
+                  \n\
+                  UU: 1 added, 1 updated\n   \
+                  Added UU_NEW.xml (modified 2023-05-30 09:29:00 UTC)\n   \
+                  Updated UU_ALP.xml from 2023-05-27 07:29:00 UTC to 2023-05-30 09:29:00 UTC (3 days 2 hours since the last change)\n\
+                  \n\
+                  WY: 1 updated\n   \
+                  Updated WY_YHB.xml to 2023-05-30 09:29:00 UTC\n");
+      for format in [NotificationFormat::Markdown, NotificationFormat::Html] {
+         let (_, body) = Templates::new(None, None, format).unwrap().render(&changes()).unwrap();
+         assert!(body.contains("UU_ALP.xml"));
+         assert!(body.contains("3 days 2 hours"));
+      }
+
+      let mut bulk = changes();
+      bulk.networks.get_mut("UU").unwrap().bulk = true;
+      for format in [NotificationFormat::Text, NotificationFormat::Markdown, NotificationFormat::Html] {
+         let (_, body) = Templates::new(None, None, format).unwrap().render(&bulk).unwrap();
+         assert!(body.contains("Bulk update of network UU (2 stations)"));
+         assert!(!body.contains("UU_ALP.xml"));
+         assert!(body.contains("WY_YHB.xml"));
+      }
+      bulk.networks.get_mut("WY").unwrap().bulk = true;
+      for format in [NotificationFormat::Text, NotificationFormat::Markdown, NotificationFormat::Html] {
+         let (_, body) = Templates::new(None, None, format).unwrap().render(&bulk).unwrap();
+         assert!(body.contains("Bulk update of network WY (1 station)"));
+      }
+   }
+
+   #[test]
+   fn test_custom_templates() {
+      let subject = String::from("{{ counts }} in {% for network in networks %}{{ network.name }} {% endfor %}");
+      let body = String::from("{% for network in networks %}{% for station in network.updated %}{{ '<' ~ station.station ~ '>' }}{% endfor %}{% endfor %}");
+      let templates = Templates::new(Some(subject), Some(body.clone()), NotificationFormat::Html).unwrap();
+      let (subject_text, body_text) = templates.render(&changes()).unwrap();
+      assert_eq!(subject_text, "1 added, 2 updated in UU WY");
+      assert_eq!(body_text, "&lt;ALP&gt;&lt;YHB&gt;");
+      let templates = Templates::new(None, Some(body), NotificationFormat::Text).unwrap();
+      assert_eq!(templates.render(&changes()).unwrap().1, "<ALP><YHB>");
+      assert!(Templates::new(Some(String::from("{% if %}")), None, NotificationFormat::Text).is_err());
+   }
+}
@@ -0,0 +1,164 @@
+//! Parses the SIS listing of a network into station modification times.
+use crate::datatypes::station_time::StationTime;
+
+/// Converts a SIS time, e.g., 2023-05-30 09:29, to UTC.
+pub fn parse_string(timestamp : &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+   use chrono::{TimeZone, Utc};
+   let invalid = || format!("'{}' is not a SIS time; expected, e.g., 2023-05-30 09:29", timestamp);
+   let (year, month, day, hour, minute) = scan_fmt::scan_fmt!(timestamp, "{d}-{d}-{d} {d}:{d}", i32, u32, u32, u32, u32)
+      .map_err(|_| invalid())?;
+   let second : u32 = 0;
+   //println!("{} {} {} {} {} {}", year, month, day, hour, minute, second);
+   Utc.with_ymd_and_hms(year, month, day, hour, minute, second).single().ok_or_else(invalid)
+}
+
+/// Extracts the station files and modification times of a network from its
+/// SIS listing.  If keeper_stations is not empty only the files whose name
+/// contains one of them are kept, e.g., BOZ keeps US_BOZ.xml and US_BOZA.xml.
+pub fn parse_page(document_text : &str,
+                  network : &str,
+                  keeper_stations : &[&str]) -> Result<Vec<StationTime>, Box<dyn std::error::Error>> {
+   let mut stations : Vec<StationTime> = Vec::new();
+   // Initialize search string e.g., UU_
+   let mut search_string : String = network.to_string();
+   search_string.push('_');
+   let selector = scraper::Selector::parse(r#"a"#).map_err(|error| format!("Invalid selector: {}", error))?;
+   // Parse the table
+   let table = table_extract::Table::find_first(document_text).ok_or("No table found in page")?;
+   for row in &table {
+       if row.is_empty() {
+          continue;
+       }
+       // The columns are icon, name, last modified, size, and description
+       if let [_, text, time, size, _] = row.as_slice() {
+          // Does this row contain something like "UU_", e.g., "UU_ALP.xml"
+          if text.contains(&search_string) {
+             // Now let's parse the tag <a href="UU_ALP.xml">UU_ALP.xml></a>
+             let table_element_fragment = scraper::Html::parse_fragment(text);
+             let station_anchor = table_element_fragment.select(&selector).next()
+                                                        .ok_or(format!("No link found in row {}", text))?;
+             let station_xml_file = station_anchor.inner_html().to_string();
+             let timestamp = parse_string(time).map_err(|error| format!("Row {}: {}", station_xml_file, error))?;
+             let mut pair = StationTime::new(&station_xml_file, timestamp);
+             // Directory listings show - for an unknown size
+             let size = size.trim();
+             if !size.is_empty() && size != "-" {
+                pair.size = Some(size.to_string());
+             }
+             let mut keep = false;
+             if !keeper_stations.is_empty() {
+                if keeper_stations.iter().any(|e| station_xml_file.contains(e)) {
+                   keep = true;
+                }
+             }
+             else {
+                keep = true;
+             }
+             if keep {         
+                stations.push(pair);
+             }
+             /*
+             for station in table_element_fragment.select(&selector) {
+                 let station_name = station.value().attr("href").expect("href not found").to_string();
+                 let time = row_slice.get(2).unwrap();
+                 let timestamp = parse_string(time); 
+                 let pair = StationTime {station: station_name.clone(), time: timestamp};
+                 stations.push(pair); 
+             }
+             */
+          }
+       }
+   }
+   Ok(stations)
+}
+
+/// Formats UTC seconds since the epoch for display.
+pub fn format_time(time : i64) -> String {
+   match chrono::DateTime::from_timestamp(time, 0) {
+      Some(date_time) => date_time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+      None => time.to_string(),
+   }
+}
+
+/// Parses a time given by a user in UTC, e.g., 2026-10-20 08:00,
+/// 2026-10-20 08:00:00 UTC as format_time writes it, or RFC 3339 such as
+/// 2026-10-20T02:00:00-06:00.
+pub fn parse_time(value : &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+   let value = value.trim();
+   if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+      return Ok(time.with_timezone(&chrono::Utc));
+   }
+   let naive = value.strip_suffix("UTC").unwrap_or(value).trim_end();
+   ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"].iter()
+      .find_map(|format| chrono::NaiveDateTime::parse_from_str(naive, format).ok())
+      .map(|time| time.and_utc())
+      .ok_or_else(|| format!("'{}' is not a time; expected, e.g., 2026-10-20 08:00 (UTC) or 2026-10-20T02:00:00-06:00", value))
+}
+
+/// Parses a duration given by a user, e.g., 90m, 6h, or 2d.  A bare number is
+/// seconds.
+pub fn parse_duration(value : &str) -> Result<chrono::Duration, String> {
+   let value = value.trim();
+   let split = value.find(|c : char| !c.is_ascii_digit()).unwrap_or(value.len());
+   let (number, unit) = value.split_at(split);
+   let seconds = match unit.trim() {
+      "" | "s" => 1,
+      "m" => 60,
+      "h" => 3600,
+      "d" => 86400,
+      _ => 0,
+   };
+   match number.parse::<i64>() {
+      Ok(number) if seconds > 0 && number > 0 => Ok(chrono::Duration::seconds(number * seconds)),
+      _ => Err(format!("'{}' is not a duration; expected, e.g., 90m, 6h, or 2d", value)),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_parse_string() {
+      assert_eq!(parse_string("2023-05-30 09:29").unwrap().timestamp(), 1685438940);
+      assert!(parse_string("2023-02-30 09:29").is_err());
+      assert!(parse_string("yesterday").is_err());
+   }
+
+   #[test]
+   fn test_parse_time() {
+      for value in ["2023-05-30 09:29", "2023-05-30 09:29:00 UTC", "2023-05-30T09:29:00Z", "2023-05-30T03:29:00-06:00"] {
+         assert_eq!(parse_time(value).unwrap().timestamp(), 1685438940);
+      }
+      assert_eq!(parse_time(&format_time(1685438940)).unwrap().timestamp(), 1685438940);
+      assert!(parse_time("tomorrow").is_err());
+      assert_eq!(parse_duration("90m").unwrap().num_seconds(), 5400);
+      assert_eq!(parse_duration("2d").unwrap().num_seconds(), 172800);
+      assert_eq!(parse_duration("30").unwrap().num_seconds(), 30);
+      assert!(parse_duration("6 weeks").is_err());
+      assert!(parse_duration("0h").is_err());
+   }
+
+   #[test]
+   fn test_parse_page() {
+      let page = r#"<table>
+         <tr><th></th><th>Name</th><th>Last modified</th><th>Size</th><th>Description</th></tr>
+         <tr><td></td><td><a href="../">Parent Directory</a></td><td></td><td>-</td><td></td></tr>
+         <tr><td></td><td><a href="US_BOZ.xml">US_BOZ.xml</a></td><td>2023-05-30 09:29</td><td>38K</td><td></td></tr>
+         <tr><td></td><td><a href="US_BOZA.xml">US_BOZA.xml</a></td><td>2023-05-30 09:30</td><td>12K</td><td></td></tr>
+      </table>"#;
+      // Keepers match anywhere in the file name
+      assert_eq!(parse_page(page, "US", &["BOZ"]).unwrap().len(), 2);
+      let stations = parse_page(page, "US", &["US_BOZ.xml"]).unwrap();
+      assert_eq!(stations.len(), 1);
+      assert_eq!(stations[0].network, "US");
+      assert_eq!(stations[0].station, "BOZ");
+      assert_eq!(stations[0].file_name, "US_BOZ.xml");
+      assert_eq!(stations[0].size.as_deref(), Some("38K"));
+      assert_eq!(stations[0].time.timestamp(), 1685438940);
+      assert_eq!(parse_page(page, "US", &[]).unwrap().len(), 2);
+      // A malformed row is an error rather than a panic
+      let page = page.replace("2023-05-30 09:30", "soon");
+      assert!(parse_page(&page, "US", &[]).is_err());
+   }
+}
@@ -0,0 +1,601 @@
+//! Runs a poll: fetch SIS, diff against the store, write, and notify.
+use crate::configuration::{self, Backend, Configuration, StormParameters};
+use crate::database::{self, migrations};
+use crate::datatypes::station_time::StationTime;
+use crate::datatypes::station_change::StationChange;
+use crate::datatypes::run_summary::RunSummary;
+use crate::datatypes::change_set::ChangeSet;
+use crate::differ::diff;
+use crate::logging;
+use crate::metrics;
+use crate::notifier::post_to_api;
+use crate::notifier::routing::{self, Notification, Route};
+use crate::parser::format_time;
+use crate::source::{fetch_sis_stations, NETWORKS};
+use crate::status::{Failure, Status};
+
+/// Prints what a poll would write and post without doing either.
+pub fn print_dry_run(changes : &ChangeSet,
+                     notifications : &[Notification],
+                     initialize : bool) {
+   println!("Dry run - nothing will be written to the database or posted to the API");
+   println!("Would create {} stations:", changes.added().count());
+   for station in changes.added() {
+      println!("   {} {}", station.file_name, format_time(station.time.timestamp()));
+   }
+   println!("Would update {} stations:", changes.updated().count());
+   for update in changes.updated() {
+      println!("   {} {}", update.station.file_name, format_time(update.new_time().timestamp()));
+   }
+   // A poll never deletes these; they stay in the database
+   println!("No longer listed by SIS, {} stations:", changes.removed().count());
+   for station in changes.removed() {
+      println!("   {} {}", station.file_name, format_time(station.time.timestamp()));
+   }
+   if initialize {
+      println!("Initialization mode - no notification would be posted");
+   }
+   else if notifications.is_empty() {
+      println!("No updates detected - no notification would be posted");
+   }
+   for notification in notifications.iter().filter(|_| !initialize) {
+      println!("Notification route: {}", notification.route.name);
+      println!("Notification subject: {}", notification.subject);
+      println!("Notification message:");
+      print!("{}", notification.message);
+   }
+}
+
+/// Renders the notification of each route with added or updated stations.
+fn render<'a>(routes : &'a [Route],
+              changes : &ChangeSet) -> Result<Vec<Notification<'a>>, Box<dyn std::error::Error>> {
+   routing::notifications(changes, routes).map_err(|error| {
+      Failure::new(Status::NotificationFailure, format!("Failed to render notification: {error}")).into()
+   })
+}
+
+/// Posts each notification to its route's topic.  Every notification is
+/// attempted even if an earlier one fails.
+fn post_notifications(configuration : &Configuration,
+                      notifications : &[Notification],
+                      metrics : &metrics::Metrics,
+                      summary : &mut RunSummary) -> Result<(), Box<dyn std::error::Error>> {
+   let Some(api) = &configuration.api else {
+      return Err(Failure::new(Status::ConfigurationError,
+                              format!("No [{}] section loaded; cannot post message", configuration::API_SECTION)).into());
+   };
+   let mut failed : Vec<&str> = Vec::new();
+   for notification in notifications.iter() {
+      let route = notification.route;
+      let random_number : u32 = rand::random_range(0..=100000);
+      let message_identifier : String = "sisUpdateMessage_".to_string()
+                                      + &random_number.to_string(); // Could also be sisTestMessage
+      let start = std::time::Instant::now();
+      let post_result = post_to_api(route.uri(api),
+                                    route.key(api),
+                                    &notification.subject,
+                                    &notification.message,
+                                    route.notification_topic(api),
+                                    route.notification_type(api),
+                                    &message_identifier);
+      match post_result {
+         Ok(post_result) => {
+            log::info!(route = route.name.as_str(), duration_ms = start.elapsed().as_millis() as u64;
+                       "Succesfully put message to API {post_result:?}");
+         }
+         Err(error) => {
+            log::warn!(route = route.name.as_str(), duration_ms = start.elapsed().as_millis() as u64;
+                       "Failed to post message to API: {error:?}");
+            metrics.record_notification_failure();
+            failed.push(&route.name);
+         }
+      }
+   }
+   if !failed.is_empty() {
+      summary.notification = String::from("failed");
+      return Err(Failure::new(Status::NotificationFailure,
+                              format!("Failed to post message to API for route(s) {}", failed.join(", "))).into());
+   }
+   summary.notification = String::from("sent");
+   Ok(())
+}
+
+/// Marks the networks in which the changes to announce cover more than the
+/// storm fraction of the stations SIS lists so the notification condenses
+/// them.  Run it on what is sent, after hold, so that held changes released
+/// together are condensed and critical stations sent alone are not.  The
+/// full list stays in the history.
+fn mark_storms(changes : &mut ChangeSet,
+               listed : &ChangeSet,
+               storm : &StormParameters) {
+   for (name, network) in changes.networks.iter_mut() {
+      let total = listed.networks.get(name)
+                        .map_or(0, |e| e.added.len() + e.updated.len() + e.unchanged.len());
+      let changed = network.added.len() + network.updated.len();
+      if total > 0 && changed as u64 >= storm.min_stations && changed as f64 > storm.fraction * total as f64 {
+         log::warn!(network = name.as_str(), stations = changed;
+                    "Bulk update of network {} ({} of {} stations); condensing the notification", name, changed, total);
+         network.bulk = true;
+      }
+   }
+}
+
+/// What to announce after a poll and the held stations it releases.
+struct Delivery {
+   announce : ChangeSet,
+   released : Vec<StationTime>,
+   held : usize,
+}
+
+/// Holds the changes that should not be announced now: every change during a
+/// maintenance window, and all but the critical stations' during quiet hours
+/// or while a digest collects.  Held changes are released once nothing mutes
+/// them and, with a digest, once the oldest has waited out the window or
+/// enough are held.
+fn hold(database : &mut database::Database,
+        configuration : &Configuration,
+        applied : &ChangeSet) -> Result<Delivery, Box<dyn std::error::Error>> {
+   let storage_failure = |database : &database::Database, error : Box<dyn std::error::Error>| -> Box<dyn std::error::Error> {
+      log::warn!("Error holding changes in {}: {error:?}", database.name());
+      Failure::new(Status::StorageFailure,
+                   format!("Failed to hold changes in {} database", database.name())).into()
+   };
+   let now = chrono::Utc::now();
+   // With auto_migrate = false the store may predate the tables; an
+   // unversioned store has neither
+   let version = database.schema_version().unwrap_or(0);
+   let mut windows = configuration.maintenance.clone();
+   if version >= migrations::MAINTENANCE_VERSION {
+      windows.extend(database.get_maintenance().map_err(|error| storage_failure(database, error))?);
+   }
+   let maintenance = windows.into_iter().find(|window| window.contains(now));
+   let quiet_hours = configuration.quiet_hours.as_ref().filter(|quiet_hours| quiet_hours.contains(now));
+   // The stations announced anyway; None if nothing is held
+   let critical : Option<&[String]> = match (&maintenance, quiet_hours, &configuration.digest) {
+      (Some(_), _, _) => Some(&[]),
+      (None, Some(quiet_hours), _) => Some(&quiet_hours.critical_stations),
+      (None, None, Some(digest)) => Some(&digest.critical_stations),
+      (None, None, None) => None,
+   };
+   if critical.is_none() && version < migrations::PENDING_VERSION {
+      // Nothing is held and nothing can have been held before
+      return Ok(Delivery {announce: applied.clone(), released: Vec::new(), held: 0});
+   }
+   let (held, mut announce) = match critical {
+      Some(critical) => (applied.filter(|station| !routing::matches_station(critical, station)),
+                         applied.filter(|station| routing::matches_station(critical, station))),
+      None => (ChangeSet::new(), applied.clone()),
+   };
+   // A station SIS no longer lists is not announced
+   let removed : Vec<StationTime> = applied.removed().cloned().collect();
+   database.remove_pending(&removed)
+      .and_then(|_| database.add_pending(&held, now.timestamp()))
+      .map_err(|error| storage_failure(database, error))?;
+   let pending = database.get_pending().map_err(|error| storage_failure(database, error))?;
+   // A critical station announced now may also be held from an earlier poll;
+   // announce it once and release its held change with it
+   let announced : Vec<&str> = announce.added().map(|station| station.file_name.as_str())
+      .chain(announce.updated().map(|update| update.station.file_name.as_str()))
+      .collect();
+   let early = pending.changes.filter(|station| announced.contains(&station.file_name.as_str()));
+   let waiting = pending.changes.filter(|station| !announced.contains(&station.file_name.as_str()));
+   let stations = |changes : &ChangeSet| -> Vec<StationTime> {
+      changes.added().cloned().chain(changes.updated().map(|update| update.station.clone())).collect()
+   };
+   let mut released = stations(&early);
+   announce.merge(early);
+   let count = (waiting.added().count() + waiting.updated().count()) as u64;
+   let age = now.timestamp() - pending.since.unwrap_or(now.timestamp());
+   let reason = match (&maintenance, quiet_hours, &configuration.digest) {
+      (Some(window), _, _) => Some(format!("until the maintenance window ends at {}{}",
+                                           format_time(window.end.timestamp()),
+                                           window.reason.as_ref().map(|e| format!(" ({})", e)).unwrap_or_default())),
+      (None, Some(_), _) => Some(String::from("until the quiet hours end")),
+      (None, None, Some(digest)) => {
+         let due = (digest.max_changes > 0 && count >= digest.max_changes)
+                   || (digest.window > 0 && age >= digest.window as i64);
+         (!due).then(|| String::from("for the digest"))
+      }
+      (None, None, None) => None,
+   };
+   if count == 0 {
+      return Ok(Delivery {announce, released, held: 0});
+   }
+   if let Some(reason) = reason {
+      log::info!("Holding {} changes {}; the oldest was detected {} ago",
+                 count, reason, crate::notifier::format_duration(age));
+      return Ok(Delivery {announce, released, held: count as usize});
+   }
+   log::info!("Releasing {} held changes; the oldest was detected {} ago",
+              count, crate::notifier::format_duration(age));
+   released.extend(stations(&waiting));
+   announce.merge(waiting);
+   Ok(Delivery {announce, released, held: 0})
+}
+
+/// Records changes to stations; a failure is logged rather than returned.
+pub fn record_history(database : &mut database::Database,
+                      action : &str,
+                      stations : &[StationTime]) {
+   let detected = chrono::Utc::now().timestamp();
+   let changes : Vec<StationChange>
+      = stations.iter().map(|station| StationChange {station: station.file_name.clone(),
+                                                     action: action.to_string(),
+                                                     time: station.time.timestamp(),
+                                                     detected}).collect();
+   if let Err(error) = database.add_history(&changes) {
+      log::warn!("Failed to record {} history in {}: {error:?}", action, database.name());
+   }
+}
+
+/// Runs one poll and emits its summary event whether or not it succeeds.
+/// Returns whether the poll found changes or only partly succeeded.
+pub fn run_poll(database : &mut database::Database,
+                configuration : &Configuration,
+                initialize : bool,
+                dry_run : bool,
+                metrics : &metrics::Metrics) -> Result<Status, Box<dyn std::error::Error>> {
+   let start = std::time::Instant::now();
+   let mut summary = RunSummary {run_id: logging::start_run(),
+                                 started: chrono::Utc::now().timestamp(),
+                                 notification: String::from("none"),
+                                 ..Default::default()};
+   let result = poll(database, configuration, initialize, dry_run, metrics, &mut summary);
+   summary.duration_ms = start.elapsed().as_millis() as u64;
+   summary.error = result.as_ref().err().map(|error| error.to_string());
+   logging::log_summary(&summary);
+   let status = if !summary.networks_failed.is_empty() || summary.failed > 0 {
+      Status::PartialFailure
+   }
+   else if summary.created + summary.updated > 0 {
+      Status::Changes
+   }
+   else {
+      Status::NoChanges
+   };
+   if !dry_run {
+      metrics.record_run(summary);
+   }
+   logging::end_run();
+   result.map(|_| status)
+}
+
+/// Runs one poll, filling in summary as it goes.  Most callers want run_poll.
+pub fn poll(database : &mut database::Database,
+            configuration : &Configuration,
+            initialize : bool,
+            dry_run : bool,
+            metrics : &metrics::Metrics,
+            summary : &mut RunSummary) -> Result<(), Box<dyn std::error::Error>> {
+   let database_stations : Vec<StationTime>;
+   if let Some(sqlite3) = &configuration.sqlite3
+      && configuration.backend == Backend::Sqlite3
+      && dry_run && !std::fs::exists(&sqlite3.file_name)? {
+      // Reading would create the sqlite3 file so treat it as empty instead
+      log::info!("sqlite3 database {} does not exist; treating as empty", sqlite3.file_name);
+      database_stations = Vec::new();
+   }
+   else {
+      log::info!("Fetching stations from {} database", database.name());
+      let start = std::time::Instant::now();
+      database_stations = match database.get_stations() {
+         Ok(result) => result,
+         Err(error) => {
+            log::warn!("Error in getting database stations from {}: {error:?}", database.name());
+            return Err(Failure::new(Status::StorageFailure,
+                                    format!("Failed getting database stations from {} database", database.name())).into());
+         }
+      };
+      metrics.record_database("get_stations", start.elapsed());
+   }
+
+   log::info!("Got {} stations from database", database_stations.len());
+
+   let (sis_stations, fetched_networks) = fetch_sis_stations(metrics);
+   summary.networks_fetched = fetched_networks.iter().map(|e| e.to_string()).collect();
+   summary.networks_failed = NETWORKS.iter().filter(|e| !fetched_networks.contains(e)).map(|e| e.to_string()).collect();
+   summary.stations_seen = sis_stations.len();
+   if fetched_networks.is_empty() {
+      return Err(Failure::new(Status::PartialFailure, "Failed to fetch any network from SIS").into());
+   }
+
+   let changes = diff(&database_stations, &sis_stations, &fetched_networks);
+   let candidate_stations_to_create : Vec<StationTime> = changes.added().cloned().collect();
+   log::info!("Will attempt to create {} stations", 
+              candidate_stations_to_create.len());
+   let candidate_stations_to_update : Vec<StationTime>
+      = changes.updated().map(|e| e.station.clone()).collect();
+   log::info!("Will attempt to update {} stations", 
+              candidate_stations_to_update.len());
+
+   metrics.record_removed(changes.removed().count());
+   summary.removed = changes.removed().count();
+
+   // Load the templates before writing so a broken template is found before
+   // any change is stored and then never announced
+   let routes = Route::load(configuration)
+      .map_err(|error| Failure::new(Status::ConfigurationError, format!("Failed to load notification templates: {error}")))?;
+   if dry_run {
+      summary.created = candidate_stations_to_create.len();
+      summary.updated = candidate_stations_to_update.len();
+      summary.notification = String::from("skipped");
+      let mut announce = changes.clone();
+      if let Some(storm) = &configuration.storm {
+         mark_storms(&mut announce, &changes, storm);
+      }
+      let notifications = render(&routes, &announce)?;
+      print_dry_run(&changes, &notifications, initialize);
+      let now = chrono::Utc::now();
+      if !initialize {
+         // Windows set with mute are in the store, which a dry run may not have
+         if let Some(window) = configuration.maintenance.iter().find(|window| window.contains(now)) {
+            println!("Maintenance window - every change would be held until {}", format_time(window.end.timestamp()));
+         }
+         else if configuration.quiet_hours.as_ref().is_some_and(|quiet_hours| quiet_hours.contains(now)) {
+            println!("Quiet hours - changes to stations that are not critical would be held until they end");
+         }
+         else if configuration.digest.is_some() {
+            println!("Digest mode - changes to stations that are not critical would be held for the digest");
+         }
+      }
+      return Ok(());
+   }
+
+   // Upsert so a row written since we read the database, e.g., by a concurrent
+   // run, is updated rather than duplicated
+   let start = std::time::Instant::now();
+   let upserted = match database.upsert_stations(&candidate_stations_to_create) {
+      Ok(result) => result,
+      Err(error) => {
+         log::warn!("Error adding stations to {}: {error:?}", database.name());
+         return Err(Failure::new(Status::StorageFailure,
+                                 format!("Failed to add stations to {} database", database.name())).into());
+      }
+   };
+   metrics.record_database("upsert_stations", start.elapsed());
+   summary.failed += upserted.failed.len();
+   let mut stations_to_create : Vec<StationTime> = upserted.inserted;
+   if !upserted.unchanged.is_empty() {
+      log::info!("{} stations to create were already up to date in {}", upserted.unchanged.len(), database.name());
+   }
+
+   let start = std::time::Instant::now();
+   let updated = match database.update_stations(&candidate_stations_to_update) {
+      Ok(result) => result,
+      Err(error) => {
+         log::warn!("Error updating stations in {}: {error:?}", database.name());
+         return Err(Failure::new(Status::StorageFailure,
+                                 format!("Failed to update stations in {} database", database.name())).into());
+      }
+   };
+   metrics.record_database("update_stations", start.elapsed());
+   summary.failed += updated.failed.len();
+   let mut stations_to_update : Vec<StationTime> = updated.updated;
+   stations_to_update.extend(upserted.updated);
+   // A row that vanished since we read the database is never reported as updated
+   if !updated.missing.is_empty() {
+      summary.missing = updated.missing.len();
+      let missing : Vec<&str> = updated.missing.iter().map(|e| e.file_name.as_str()).collect();
+      if configuration.create_missing {
+         log::warn!("{} stations to update were missing from {}; creating them: {}",
+                    missing.len(), database.name(), missing.join(", "));
+         match database.upsert_stations(&updated.missing) {
+            Ok(result) => {
+               summary.failed += result.failed.len();
+               stations_to_create.extend(result.inserted);
+               stations_to_update.extend(result.updated);
+            }
+            Err(error) => {
+               log::warn!("Error creating missing stations in {}: {error:?}", database.name());
+               return Err(Failure::new(Status::StorageFailure,
+                                       format!("Failed to create missing stations in {} database", database.name())).into());
+            }
+         }
+      }
+      else {
+         log::warn!("{} stations to update were missing from {} and were not updated: {}",
+                    missing.len(), database.name(), missing.join(", "));
+      }
+   }
+   log::info!("Created {} stations in {}", stations_to_create.len(), database.name());
+   log::info!("Updated {} stations in {}", stations_to_update.len(), database.name());
+   metrics.record_changes("created", stations_to_create.len());
+   metrics.record_changes("updated", stations_to_update.len());
+   summary.created = stations_to_create.len();
+   summary.updated = stations_to_update.len();
+
+   // Announce what was written rather than what the diff expected
+   let mut applied = ChangeSet::new();
+   for station in stations_to_create.iter() {
+      applied.add(station.clone());
+   }
+   for station in stations_to_update.iter() {
+      let old_time = database_stations.iter().find(|e| e.file_name == station.file_name).map(|e| e.time);
+      applied.update(old_time, station.clone());
+   }
+   for station in changes.removed() {
+      applied.remove(station.clone());
+   }
+
+   if !initialize {
+      record_history(database, "created", &stations_to_create);
+      record_history(database, "updated", &stations_to_update);
+      let mut delivery = hold(database, configuration, &applied)?;
+      if let Some(storm) = &configuration.storm {
+         mark_storms(&mut delivery.announce, &changes, storm);
+      }
+      let notifications = render(&routes, &delivery.announce)?;
+      if !notifications.is_empty() {
+         post_notifications(configuration, &notifications, metrics, summary)?;
+      }
+      else if delivery.held > 0 {
+         summary.notification = String::from("held");
+      }
+      else {
+         log::info!("No updates detected");
+      }
+      // Released only once posted so a failed digest is retried next poll
+      if let Err(error) = database.remove_pending(&delivery.released) {
+         log::warn!("Error releasing held changes in {}: {error:?}", database.name());
+         return Err(Failure::new(Status::StorageFailure,
+                                 format!("Failed to release the digest's changes in {} database", database.name())).into());
+      }
+   }
+   else {
+      summary.notification = String::from("skipped");
+      log::info!("Initialization mode - no updates posted to API");
+   }
+   Ok(())
+}
+
+/// Polls every poll_interval seconds, serving metrics and health checks if
+/// metrics_address is set.  Only returns if the metrics server cannot start.
+pub fn run_daemon(database : &mut database::Database,
+                  configuration : &Configuration,
+                  metrics : &std::sync::Arc<metrics::Metrics>) -> Result<(), Box<dyn std::error::Error>> {
+   if let Some(address) = &configuration.metrics_address {
+      let max_age = configuration.poll_interval * configuration.ready_intervals;
+      let readiness = metrics::server::Readiness {probe: database.probe(),
+                                                  max_age: std::time::Duration::from_secs(max_age)};
+      metrics::server::start(address, metrics.clone(), readiness)?;
+   }
+   let interval = std::time::Duration::from_secs(configuration.poll_interval);
+   let lock_timeout = std::time::Duration::from_secs(configuration.lock_timeout);
+   log::info!("Polling every {} seconds", configuration.poll_interval);
+   loop {
+      let start = std::time::Instant::now();
+      // Lock each poll rather than the whole daemon so reset and migrate can run in between
+      match database.lock(lock_timeout) {
+         Ok(Some(_lock)) => {
+            let result = run_poll(database, configuration, false, false, metrics);
+            if let Err(error) = &result {
+               log::warn!("Poll failed: {}", error);
+            }
+            // A poll that fetched only some networks is not a success
+            metrics.record_poll(result.is_ok_and(|status| status.is_success()));
+         }
+         Ok(None) => {
+            log::warn!("Another sis_poller instance is using the {} database; skipping this poll", database.name());
+         }
+         Err(error) => {
+            log::warn!("Error locking {} database: {error:?}", database.name());
+            metrics.record_poll(false);
+         }
+      }
+      std::thread::sleep(interval.saturating_sub(start.elapsed()));
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn load(name : &str, sections : &str) -> (Configuration, String) {
+      let directory = std::env::temp_dir();
+      let file_name = directory.join(format!("sis_poller_poll_{}_{}.sqlite3", name, std::process::id()));
+      let ini_file = directory.join(format!("sis_poller_poll_{}_{}.ini", name, std::process::id()));
+      let _ = std::fs::remove_file(&file_name);
+      std::fs::write(&ini_file, format!("[SISSqlite3Database]\nfile_name = {}\n{}", file_name.display(), sections)).unwrap();
+      let configuration = Configuration::load(ini_file.to_str().unwrap(), None, configuration::ApiSection::Ignored).unwrap();
+      std::fs::remove_file(&ini_file).unwrap();
+      (configuration, file_name.to_str().unwrap().to_string())
+   }
+
+   fn station(file_name : &str, time : i64) -> StationTime {
+      StationTime::from_timestamp(file_name, time).unwrap()
+   }
+
+   #[test]
+   fn test_hold_without_migrations() {
+      let mut applied = ChangeSet::new();
+      applied.add(station("UU_NEW.xml", 20));
+      applied.remove(station("UU_OLD.xml", 10));
+
+      // Nothing to hold, so a store that was never migrated is not touched
+      let (configuration, file_name) = load("unmigrated", "");
+      let mut database = database::Database::sqlite3(&file_name, false);
+      let delivery = hold(&mut database, &configuration, &applied).unwrap();
+      assert_eq!(delivery.announce, applied);
+      assert!(delivery.released.is_empty());
+      assert_eq!(delivery.held, 0);
+      let _ = std::fs::remove_file(&file_name);
+
+      // A digest needs the pending table
+      let (configuration, file_name) = load("unmigrated_digest", "[Digest]\nwindow = 3600\n");
+      let mut database = database::Database::sqlite3(&file_name, false);
+      assert!(hold(&mut database, &configuration, &applied).is_err());
+      let _ = std::fs::remove_file(&file_name);
+   }
+
+   #[test]
+   fn test_hold_pending_critical_station() {
+      let (configuration, file_name) = load("critical", "[Digest]\nwindow = 3600\ncritical_stations = UU.ALP\n");
+      let mut database = database::Database::sqlite3(&file_name, true);
+      let now = chrono::Utc::now().timestamp();
+      // Held before UU.ALP was made critical
+      let mut held = ChangeSet::new();
+      held.update(Some(station("UU_ALP.xml", 10).time), station("UU_ALP.xml", 20));
+      held.update(Some(station("WY_YHB.xml", 10).time), station("WY_YHB.xml", 20));
+      database.add_pending(&held, now - 60).unwrap();
+
+      let mut applied = ChangeSet::new();
+      applied.update(Some(station("UU_ALP.xml", 20).time), station("UU_ALP.xml", 30));
+      let delivery = hold(&mut database, &configuration, &applied).unwrap();
+      // Announced once, from the held change's stored time to the newest time
+      assert_eq!(delivery.announce.updated().count(), 1);
+      let update = delivery.announce.updated().next().unwrap();
+      assert_eq!(update.old_time.unwrap().timestamp(), 10);
+      assert_eq!(update.new_time().timestamp(), 30);
+      assert_eq!(delivery.released.len(), 1);
+      assert_eq!(delivery.released[0].file_name, "UU_ALP.xml");
+      assert_eq!(delivery.held, 1);
+      database.remove_pending(&delivery.released).unwrap();
+      let pending = database.get_pending().unwrap();
+      assert_eq!(pending.changes.updated().map(|e| e.station.file_name.as_str()).collect::<Vec<_>>(), vec!["WY_YHB.xml"]);
+      let _ = std::fs::remove_file(&file_name);
+   }
+
+   #[test]
+   fn test_storms_on_announced() {
+      let (configuration, file_name) = load("storm", "[Storm]\nfraction = 0.5\nmin_stations = 3\n[Digest]\nmax_changes = 8\ncritical_stations = UU.S0\n");
+      let storm = configuration.storm.clone().unwrap();
+      let mut database = database::Database::sqlite3(&file_name, true);
+      let mut listed = ChangeSet::new();
+      for index in 0..10 {
+         listed.keep(station(&format!("UU_S{}.xml", index), 10));
+      }
+      for index in 0..4 {
+         listed.keep(station(&format!("WY_S{}.xml", index), 10));
+      }
+      let mut applied = ChangeSet::new();
+      for index in 0..4 {
+         applied.update(Some(station(&format!("UU_S{}.xml", index), 10).time), station(&format!("UU_S{}.xml", index), 20));
+      }
+      applied.update(Some(station("WY_S0.xml", 10).time), station("WY_S0.xml", 20));
+      // 4 of 10 UU stations is under the fraction and 1 WY station is under min_stations
+      mark_storms(&mut applied, &listed, &storm);
+      assert!(!applied.networks["UU"].bulk && !applied.networks["WY"].bulk);
+
+      // A storm held but for a critical station announces that station on its own
+      let mut applied = ChangeSet::new();
+      for index in 0..8 {
+         applied.update(Some(station(&format!("UU_S{}.xml", index), 10).time), station(&format!("UU_S{}.xml", index), 20));
+      }
+      let mut delivery = hold(&mut database, &configuration, &applied).unwrap();
+      mark_storms(&mut delivery.announce, &listed, &storm);
+      assert_eq!(delivery.announce.networks["UU"].updated.len(), 1);
+      assert!(!delivery.announce.networks["UU"].bulk);
+      assert_eq!(delivery.held, 7);
+
+      // and the rest are condensed when the digest releases them
+      let mut applied = ChangeSet::new();
+      applied.update(Some(station("WY_S0.xml", 10).time), station("WY_S0.xml", 20));
+      let mut delivery = hold(&mut database, &configuration, &applied).unwrap();
+      mark_storms(&mut delivery.announce, &listed, &storm);
+      assert_eq!(delivery.held, 0);
+      assert_eq!(delivery.announce.networks["UU"].updated.len(), 7);
+      assert!(delivery.announce.networks["UU"].bulk);
+      assert!(!delivery.announce.networks["WY"].bulk);
+      let _ = std::fs::remove_file(&file_name);
+   }
+}
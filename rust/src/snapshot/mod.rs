@@ -0,0 +1,254 @@
+//! Exports the stored stations and history to CSV or JSON and reads them
+//! back, e.g., to move between backends or seed a new deployment.
+use chrono::{DateTime, Utc};
+use crate::datatypes::station_time::StationTime;
+use crate::datatypes::station_change::StationChange;
+
+/// The file format of an export.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum SnapshotFormat {
+   Csv,
+   Json,
+}
+
+impl SnapshotFormat {
+   /// The format named by a file's extension, e.g., stations.csv.
+   pub fn from_file_name(file_name : &str) -> Option<SnapshotFormat> {
+      match std::path::Path::new(file_name).extension()?.to_str()?.to_lowercase().as_str() {
+         "csv" => Some(SnapshotFormat::Csv),
+         "json" => Some(SnapshotFormat::Json),
+         _ => None,
+      }
+   }
+}
+
+/// How an import treats what is already stored.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ImportMode {
+   /// Keep stored stations the file does not list or lists with an older time,
+   /// and add history not yet stored
+   Merge,
+   /// Remove every stored station, and the history if the file has any, first
+   Replace,
+}
+
+/// A history entry as exported.  Times are written as RFC 3339 rather than
+/// seconds since the epoch so the file can be read by people.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HistoryRecord {
+   xml_file : String,
+   action : String,
+   last_modified : DateTime<Utc>,
+   detected : DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonSnapshot {
+   stations : Vec<StationTime>,
+   #[serde(default)]
+   history : Vec<HistoryRecord>,
+}
+
+/// One CSV row.  Stations and history share the file; record says which a
+/// row is, and stations leave action and detected empty.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CsvRecord {
+   record : String,
+   xml_file : String,
+   last_modified : DateTime<Utc>,
+   action : Option<String>,
+   detected : Option<DateTime<Utc>>,
+}
+
+/// The contents of the xml_update and xml_update_history tables.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+   pub stations : Vec<StationTime>,
+   pub history : Vec<StationChange>,
+}
+
+fn to_time(time : i64, xml_file : &str) -> Result<DateTime<Utc>, String> {
+   DateTime::from_timestamp(time, 0).ok_or_else(|| format!("Invalid time {} for {}", time, xml_file))
+}
+
+fn to_record(change : &StationChange) -> Result<HistoryRecord, String> {
+   Ok(HistoryRecord {xml_file: change.station.clone(),
+                     action: change.action.clone(),
+                     last_modified: to_time(change.time, &change.station)?,
+                     detected: to_time(change.detected, &change.station)?})
+}
+
+fn from_record(record : HistoryRecord) -> StationChange {
+   StationChange {station: record.xml_file,
+                  action: record.action,
+                  time: record.last_modified.timestamp(),
+                  detected: record.detected.timestamp()}
+}
+
+impl Snapshot {
+   pub fn write(&self, format : SnapshotFormat) -> Result<String, Box<dyn std::error::Error>> {
+      let history = self.history.iter().map(to_record).collect::<Result<Vec<_>, _>>()?;
+      match format {
+         SnapshotFormat::Json => {
+            let snapshot = JsonSnapshot {stations: self.stations.clone(), history};
+            Ok(serde_json::to_string_pretty(&snapshot)? + "\n")
+         }
+         SnapshotFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for station in self.stations.iter() {
+               writer.serialize(CsvRecord {record: String::from("station"),
+                                           xml_file: station.file_name.clone(),
+                                           last_modified: station.time,
+                                           action: None,
+                                           detected: None})?;
+            }
+            for record in history {
+               writer.serialize(CsvRecord {record: String::from("history"),
+                                           xml_file: record.xml_file,
+                                           last_modified: record.last_modified,
+                                           action: Some(record.action),
+                                           detected: Some(record.detected)})?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+         }
+      }
+   }
+
+   /// Splits the stations for a merge into those to upsert and those stored
+   /// with a newer time.  The newer stored times are kept; rolling them back
+   /// would announce the stations again on the next poll.
+   pub fn merge_stations(&self, stored : &[StationTime]) -> (Vec<StationTime>, Vec<StationTime>) {
+      let stored : std::collections::HashMap<&str, DateTime<Utc>>
+         = stored.iter().map(|station| (station.file_name.as_str(), station.time)).collect();
+      self.stations.iter().cloned()
+          .partition(|station| stored.get(station.file_name.as_str()).is_none_or(|time| *time <= station.time))
+   }
+
+   /// Parses and validates an export.  Every invalid entry is reported at once.
+   pub fn read(text : &str, format : SnapshotFormat) -> Result<Snapshot, Box<dyn std::error::Error>> {
+      let snapshot = match format {
+         SnapshotFormat::Json => {
+            let snapshot : JsonSnapshot = serde_json::from_str(text)?;
+            Snapshot {stations: snapshot.stations,
+                      history: snapshot.history.into_iter().map(from_record).collect()}
+         }
+         SnapshotFormat::Csv => {
+            let mut snapshot = Snapshot::default();
+            let mut reader = csv::Reader::from_reader(text.as_bytes());
+            for (index, row) in reader.deserialize::<CsvRecord>().enumerate() {
+               // The header is line 1
+               let line = index + 2;
+               let row = row.map_err(|error| format!("Line {}: {}", line, error))?;
+               match (row.record.as_str(), row.action, row.detected) {
+                  ("station", None, None) => snapshot.stations.push(StationTime::new(&row.xml_file, row.last_modified)),
+                  ("history", Some(action), Some(detected)) => {
+                     snapshot.history.push(from_record(HistoryRecord {xml_file: row.xml_file,
+                                                                      action,
+                                                                      last_modified: row.last_modified,
+                                                                      detected}));
+                  }
+                  ("station", _, _) => {
+                     return Err(format!("Line {}: a station record has no action or detected time", line).into());
+                  }
+                  ("history", _, _) => {
+                     return Err(format!("Line {}: a history record needs an action and detected time", line).into());
+                  }
+                  (record, _, _) => {
+                     return Err(format!("Line {}: record is '{}'; expected station or history", line, record).into());
+                  }
+               }
+            }
+            snapshot
+         }
+      };
+      snapshot.validate()?;
+      Ok(snapshot)
+   }
+
+   fn validate(&self) -> Result<(), String> {
+      let mut problems : Vec<String> = Vec::new();
+      let mut seen = std::collections::HashSet::new();
+      for station in self.stations.iter() {
+         if let Err(problem) = station.validate() {
+            problems.push(problem);
+         }
+         if !seen.insert(station.file_name.as_str()) {
+            problems.push(format!("{} is listed more than once", station.file_name));
+         }
+      }
+      for change in self.history.iter() {
+         if let Err(problem) = StationTime::new(&change.station, DateTime::<Utc>::UNIX_EPOCH).validate() {
+            problems.push(format!("History: {}", problem));
+         }
+         if change.action.trim().is_empty() {
+            problems.push(format!("History: {} has no action", change.station));
+         }
+      }
+      if !problems.is_empty() {
+         return Err(format!("Invalid export: {}", problems.join("; ")));
+      }
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn snapshot() -> Snapshot {
+      let mut station = StationTime::from_timestamp("UU_ALP.xml", 1685438940).unwrap();
+      station.size = Some(String::from("38K"));
+      Snapshot {stations: vec![station, StationTime::from_timestamp("WY_YHB.xml", 1685439000).unwrap()],
+                history: vec![StationChange {station: String::from("UU_ALP.xml"),
+                                             action: String::from("updated"),
+                                             time: 1685438940,
+                                             detected: 1685439600}]}
+   }
+
+   #[test]
+   fn test_round_trip() {
+      let json = snapshot().write(SnapshotFormat::Json).unwrap();
+      assert!(json.contains("\"time\": \"2023-05-30T09:29:00Z\""));
+      assert_eq!(Snapshot::read(&json, SnapshotFormat::Json).unwrap(), snapshot());
+      let csv = snapshot().write(SnapshotFormat::Csv).unwrap();
+      assert_eq!(csv.lines().next().unwrap(), "record,xml_file,last_modified,action,detected");
+      assert_eq!(csv.lines().nth(1).unwrap(), "station,UU_ALP.xml,2023-05-30T09:29:00Z,,");
+      let read = Snapshot::read(&csv, SnapshotFormat::Csv).unwrap();
+      // CSV does not carry the size
+      assert_eq!(read.stations[0].size, None);
+      assert_eq!(read.history, snapshot().history);
+      assert_eq!(SnapshotFormat::from_file_name("stations.CSV"), Some(SnapshotFormat::Csv));
+      assert_eq!(SnapshotFormat::from_file_name("stations"), None);
+   }
+
+   #[test]
+   fn test_merge_stations() {
+      let stored = vec![StationTime::from_timestamp("UU_ALP.xml", 1685439000).unwrap(),
+                        StationTime::from_timestamp("WY_YHB.xml", 1685438940).unwrap()];
+      // UU_ALP.xml is stored with a newer time than the file's
+      let (upsert, newer) = snapshot().merge_stations(&stored);
+      assert_eq!(upsert.iter().map(|e| e.file_name.as_str()).collect::<Vec<_>>(), vec!["WY_YHB.xml"]);
+      assert_eq!(newer.iter().map(|e| e.file_name.as_str()).collect::<Vec<_>>(), vec!["UU_ALP.xml"]);
+      let (upsert, newer) = snapshot().merge_stations(&[]);
+      assert_eq!(upsert.len(), 2);
+      assert!(newer.is_empty());
+   }
+
+   #[test]
+   fn test_validation() {
+      let csv = "record,xml_file,last_modified,action,detected\n\
+                 station,UU_ALP.xml,2023-05-30T09:29:00Z,,\n\
+                 station,UU_ALP.xml,2023-05-30T09:30:00Z,,\n\
+                 station,ALP,2023-05-30T09:29:00Z,,\n";
+      let error = Snapshot::read(csv, SnapshotFormat::Csv).unwrap_err().to_string();
+      assert!(error.contains("UU_ALP.xml is listed more than once"));
+      assert!(error.contains("ALP is not a station file name"));
+      let csv = "record,xml_file,last_modified,action,detected\nstation,UU_ALP.xml,yesterday,,\n";
+      assert!(Snapshot::read(csv, SnapshotFormat::Csv).unwrap_err().to_string().starts_with("Line 2"));
+      let csv = "record,xml_file,last_modified,action,detected\nhistory,UU_ALP.xml,2023-05-30T09:29:00Z,,\n";
+      assert!(Snapshot::read(csv, SnapshotFormat::Csv).is_err());
+      let json = snapshot().write(SnapshotFormat::Json).unwrap().replace("\"station\": \"ALP\"", "\"station\": \"BGU\"");
+      assert!(Snapshot::read(&json, SnapshotFormat::Json).unwrap_err().to_string().contains("station BGU"));
+      assert!(Snapshot::read("{\"stations\": []}", SnapshotFormat::Json).unwrap().history.is_empty());
+   }
+}
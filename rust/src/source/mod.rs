@@ -0,0 +1,96 @@
+//! Fetches the SIS listing of each network.
+use crate::datatypes::station_time::StationTime;
+use crate::metrics;
+use crate::parser::parse_page;
+
+/// Returns the HTTP status and, if it was 200, the page.
+pub fn get_page(uri : &str) -> Result<(u16, Option<String>), Box<dyn std::error::Error>> {
+   let response = reqwest::blocking::get(uri)?;
+   let status = response.status().as_u16();
+   // If I got a 200 code then return a win
+   if response.status() == 200 {
+      log::info!("Successfully hit URL");
+      let document_text = response.text()?.clone();
+      return Ok((status, Some(document_text)));
+   }
+   Ok((status, None))
+}
+
+/// The SIS directory with one listing per network.
+pub static BASE_URI: &str = "https://files.anss-sis.scsn.org/production/FDSNStationXML1.1/";
+
+/// The SIS URL of a network's station XML file.
+pub fn file_url(network : &str, file_name : &str) -> String {
+   format!("{}{}/{}", BASE_URI, network, file_name)
+}
+
+/// The SIS networks that are polled.
+pub static NETWORKS: [&str; 6] = ["UU", "WY", "IW", "US", "C0", "NN"];
+
+/// Fetches and parses every network in NETWORKS.  Returns the stations
+/// and the networks that were fetched; a failed network is logged and skipped.
+pub fn fetch_sis_stations(metrics : &metrics::Metrics) -> (Vec<StationTime>, Vec<&'static str>) {
+   let iw_keeper_stations = vec!["FLWY", "IMW", "LOHW", "MOOW", "REDW", "RWWY", "SNOW", "TPAW"];
+   let us_keeper_stations = vec!["AHID", "BOZ", "BW06", "DUG",  "ELK",  "HLID", "HWUT", "ISCO", "LKWY", "MVCO", "TPNV", "WUAZ"];
+   let c0_keeper_stations = vec!["MOFF"];
+   let nn_keeper_stations = vec!["PIO", "V12A", "R11B", "PRN", "SHP", "WTNK", "SPR3", "Q12A"];
+   let mut sis_stations : Vec<StationTime> = Vec::new();
+   let mut fetched_networks : Vec<&'static str> = Vec::new();
+   for network in NETWORKS.iter() {
+       let mut uri : String = BASE_URI.to_string();
+       if !uri.ends_with('/') {
+          uri.push('/');
+       }
+       uri.push_str(network);
+       log::info!(network = *network; "Fetching data from URI: {}", uri);
+       let start = std::time::Instant::now();
+       let html_text_result = get_page(&uri);
+       match html_text_result {
+          Ok((status, None)) => {
+             log::warn!(network = *network, http_status = status; "Fetching {} returned HTTP status {}", uri, status);
+             metrics.record_fetch(network, start.elapsed(), status, 0);
+             continue;
+          }
+          Ok((status, Some(html_text))) => {
+             log::debug!("Parsing HTML...");
+             let mut keeper_stations : Vec<&str> = Vec::new();
+             if *network == "IW" {
+                keeper_stations = iw_keeper_stations.clone();
+             }
+             else if *network == "US" {
+                keeper_stations = us_keeper_stations.clone();
+             }
+             else if *network == "C0" {
+                keeper_stations = c0_keeper_stations.clone();
+             }
+             else if *network == "NN" {
+                keeper_stations = nn_keeper_stations.clone();
+             }
+             let stations = match parse_page(&html_text, network, &keeper_stations) {
+                Ok(stations) => stations,
+                Err(error) => {
+                   // Treat the network as unfetched so its stations are not reported as removed
+                   log::warn!(network = *network; "Error parsing HTML for network {}: {}", network, error);
+                   metrics.record_parse_error();
+                   metrics.record_fetch(network, start.elapsed(), status, 0);
+                   continue;
+                }
+             };
+             log::info!(network = *network,
+                        stations = stations.len(),
+                        duration_ms = start.elapsed().as_millis() as u64;
+                        "Unpacked {} stations for network {}", stations.len(), network);
+             metrics.record_fetch(network, start.elapsed(), status, stations.len());
+             sis_stations.extend(stations); 
+             fetched_networks.push(network);
+          }
+          Err(error) => {
+             log::warn!(network = *network; "Error in getting HTML: {error:?}");
+             metrics.record_fetch(network, start.elapsed(), 0, 0);
+             continue;
+          }
+       }
+   } 
+   log::debug!("Returned {} stations from SIS", sis_stations.len());
+   (sis_stations, fetched_networks)
+}
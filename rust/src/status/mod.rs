@@ -0,0 +1,76 @@
+/// The documented process exit statuses.  When several apply the most severe
+/// wins, in the order configuration, storage, notification, partial failure,
+/// changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+   /// The run succeeded and found no changes
+   NoChanges = 0,
+   /// An error without a more specific status, e.g., an unknown station
+   Error = 1,
+   /// The run succeeded and found changes
+   Changes = 3,
+   /// Some networks could not be fetched or parsed, or some rows could not be written
+   PartialFailure = 4,
+   /// The database could not be read, written, locked, or migrated
+   StorageFailure = 5,
+   /// The notification API did not accept the message
+   NotificationFailure = 6,
+   /// The configuration is invalid
+   ConfigurationError = 7,
+   /// Another instance holds the run lock (EX_TEMPFAIL)
+   Locked = 75,
+}
+
+impl Status {
+   pub fn code(self) -> u8 {
+      self as u8
+   }
+
+   /// Whether a run with this status fully succeeded; a partial failure did not.
+   pub fn is_success(self) -> bool {
+      matches!(self, Status::NoChanges | Status::Changes)
+   }
+}
+
+/// An error that determines the exit status.
+#[derive(Debug)]
+pub struct Failure {
+   pub status : Status,
+   pub message : String,
+}
+
+impl Failure {
+   pub fn new(status : Status, message : impl Into<String>) -> Failure {
+      Failure {status, message: message.into()}
+   }
+}
+
+impl std::fmt::Display for Failure {
+   fn fmt(&self, formatter : &mut std::fmt::Formatter) -> std::fmt::Result {
+      write!(formatter, "{}", self.message)
+   }
+}
+
+impl std::error::Error for Failure {}
+
+/// The exit status for an error; Error unless it is a Failure.
+pub fn status_of(error : &(dyn std::error::Error + 'static)) -> Status {
+   error.downcast_ref::<Failure>().map(|failure| failure.status).unwrap_or(Status::Error)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn test_status_of() {
+      let error : Box<dyn std::error::Error> = Failure::new(Status::StorageFailure, "Failed to update stations").into();
+      assert_eq!(status_of(error.as_ref()), Status::StorageFailure);
+      assert_eq!(error.to_string(), "Failed to update stations");
+      let error : Box<dyn std::error::Error> = "Station not found".into();
+      assert_eq!(status_of(error.as_ref()), Status::Error);
+      assert_eq!(Status::Locked.code(), 75);
+      assert!(Status::NoChanges.is_success() && Status::Changes.is_success());
+      assert!(!Status::PartialFailure.is_success());
+   }
+}